@@ -1,12 +1,18 @@
-use crate::config::Config;
+use crate::config::{render_template, Battery, Config, Threshold, WarnAction};
 use crate::netlink::utils as ev_utils;
 use crate::netlink::{NetlinkError, NetlinkHandle, Uevent};
-use crate::notif::{Notification, Timeout, Urgency};
+use crate::notif::{self, Hint, Notification, Timeout};
+use crate::Module;
+use std::collections::VecDeque;
 use std::fs;
 use std::io::ErrorKind;
+use std::process::Command;
 use std::str::FromStr;
 
 const SYS_PATH: &'static str = "/sys/class/power_supply/{name}/uevent";
+const SYS_CLASS_PATH: &'static str = "/sys/class/power_supply";
+const TREND_SAMPLES: usize = 6;
+const TREND_THRESHOLD: f32 = 0.1; // relative change of draw considered as trend
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 enum Status {
@@ -20,36 +26,179 @@ enum Status {
 struct UeventPowerSupply {
     status: Status,
     capacity: u8,
+    minutes_left: Option<u32>,
+    power: Option<f32>,
+}
+
+enum PowerSupplyEvent {
+    Battery(UeventPowerSupply),
+    Adapter(bool), // online
+}
+
+struct BatteryReading {
+    status: Status,
+    capacity: f32,
+    weight: f32, // full charge, so bigger battery affects total capacity more
+    energy_now: Option<f32>,
+    energy_full: Option<f32>,
+    power: Option<f32>,
+}
+
+fn read_battery(name: &str) -> Result<BatteryReading, String> {
+    let uevent_str =
+        fs::read_to_string(SYS_PATH.replace("{name}", name)).map_err(|e| e.to_string())?;
+    let status = ev_utils::get_element_val(&uevent_str, "POWER_SUPPLY_STATUS")
+        .ok_or("POWER_SUPPLY_STATUS missing".to_owned())?
+        .into();
+    let get_f32 = |name| {
+        ev_utils::get_element_val(&uevent_str, name)
+            .ok_or(format!("{name} missing"))
+            .map(|val| f32::from_str(&val))?
+            .map_err(|err| err.to_string())
+    };
+    let full = get_f32("POWER_SUPPLY_ENERGY_FULL").or(get_f32("POWER_SUPPLY_CHARGE_FULL"));
+
+    // charge (µAh) and current (µA) are turned into energy (µWh) and power (µW)
+    let voltage = get_f32("POWER_SUPPLY_VOLTAGE_NOW").ok();
+    let to_energy = |charge: f32| voltage.map(|voltage| charge * voltage / 1_000_000.);
+    let energy = |energy_name, charge_name| {
+        get_f32(energy_name)
+            .ok()
+            .or(get_f32(charge_name).ok().and_then(to_energy))
+    };
+    let energy_now = energy("POWER_SUPPLY_ENERGY_NOW", "POWER_SUPPLY_CHARGE_NOW");
+    let energy_full = energy("POWER_SUPPLY_ENERGY_FULL", "POWER_SUPPLY_CHARGE_FULL");
+    let power = get_f32("POWER_SUPPLY_POWER_NOW")
+        .ok()
+        .or(get_f32("POWER_SUPPLY_CURRENT_NOW").ok().and_then(to_energy))
+        .map(f32::abs); // some drivers report negative values while discharging
+
+    let capacity = match ev_utils::get_element_val(&uevent_str, "POWER_SUPPLY_CAPACITY") {
+        Some(capacity) => u8::from_str(&capacity).map_err(|err| err.to_string())? as f32,
+        None => get_f32("POWER_SUPPLY_ENERGY_NOW")? / full.clone()? * 100.,
+    };
+
+    Ok(BatteryReading {
+        status,
+        capacity,
+        weight: full.unwrap_or(1.),
+        energy_now,
+        energy_full,
+        power,
+    })
+}
+
+// rounded to 5 minutes, so it doesn't jitter on every reading
+fn estimate_minutes(status: &Status, batteries: &[BatteryReading]) -> Option<u32> {
+    let sum =
+        |get: fn(&BatteryReading) -> Option<f32>| batteries.iter().map(get).sum::<Option<f32>>();
+    let now = sum(|battery| battery.energy_now)?;
+    let power = sum(|battery| battery.power).filter(|power| *power > 0.)?;
+    let energy = match status {
+        Status::Discharging => now,
+        Status::Charging => sum(|battery| battery.energy_full)? - now,
+        _ => return None,
+    };
+    let minutes = (energy.max(0.) / power * 60. / 5.).round() as u32 * 5;
+
+    (minutes > 0).then_some(minutes)
+}
+
+// compares older and newer halves of samples, so single spikes don't flip it
+fn power_trend(samples: &VecDeque<f32>) -> Option<&'static str> {
+    if samples.len() < TREND_SAMPLES {
+        return None;
+    }
+
+    let half = samples.len() / 2;
+    let older = samples.iter().take(half).sum::<f32>() / half as f32;
+    let newer = samples.iter().skip(half).sum::<f32>() / (samples.len() - half) as f32;
+
+    if older <= 0. {
+        None
+    } else if newer > older * (1. + TREND_THRESHOLD) {
+        Some("↑")
+    } else if newer < older * (1. - TREND_THRESHOLD) {
+        Some("↓")
+    } else {
+        Some("→")
+    }
+}
+
+fn trend_suffix(
+    config_battery: &Battery,
+    samples: &mut VecDeque<f32>,
+    power: Option<f32>,
+) -> String {
+    if let Some(power) = power {
+        if samples.len() == TREND_SAMPLES {
+            samples.pop_front();
+        }
+
+        samples.push_back(power);
+    }
+
+    match power_trend(samples) {
+        Some(trend) if config_battery.show_trend => format!(" {trend}"),
+        _ => String::new(),
+    }
+}
+
+fn format_minutes(minutes: u32) -> String {
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("~{minutes}m"),
+        (hours, 0) => format!("~{hours}h"),
+        (hours, minutes) => format!("~{hours}h{minutes}m"),
+    }
 }
 
 impl UeventPowerSupply {
     pub fn new() -> Result<Self, String> {
-        let uevent_str =
-            fs::read_to_string(SYS_PATH.replace("{name}", &Config::get().battery.target))
-                .map_err(|e| e.to_string())?;
-        let status = ev_utils::get_element_val(&uevent_str, "POWER_SUPPLY_STATUS")
-            .ok_or("POWER_SUPPLY_STATUS missing".to_owned())?
-            .into();
-
-        if let Some(capacity) = ev_utils::get_element_val(&uevent_str, "POWER_SUPPLY_CAPACITY") {
-            Ok(Self {
-                status,
-                capacity: u8::from_str(&capacity).map_err(|err| err.to_string())?,
-            })
+        let batteries = Config::get()
+            .battery
+            .target
+            .iter()
+            .map(|name| read_battery(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let first = batteries
+            .first()
+            .ok_or("battery target is empty".to_owned())?;
+        let total_weight = batteries.iter().map(|battery| battery.weight).sum::<f32>();
+        let capacity = batteries
+            .iter()
+            .map(|battery| battery.capacity * battery.weight)
+            .sum::<f32>()
+            / total_weight;
+        let any = |status| batteries.iter().any(|battery| battery.status == status);
+
+        let status = if any(Status::Charging) {
+            Status::Charging
+        } else if batteries
+            .iter()
+            .all(|battery| battery.status == Status::Full)
+        {
+            Status::Full
+        } else if any(Status::Discharging) {
+            Status::Discharging
         } else {
-            let now = ev_utils::get_element_val(&uevent_str, "POWER_SUPPLY_ENERGY_NOW")
-                .ok_or("POWER_SUPPLY_ENERGY_NOW missing".to_owned())
-                .map(|now| f32::from_str(&now))?
-                .map_err(|err| err.to_string())?;
-            let full = ev_utils::get_element_val(&uevent_str, "POWER_SUPPLY_ENERGY_FULL")
-                .ok_or("POWER_SUPPLY_ENERGY_FULL missing".to_owned())
-                .map(|now| f32::from_str(&now))?
-                .map_err(|err| err.to_string())?;
-
-            Ok(Self {
-                status,
-                capacity: (now / full * 100.) as u8,
-            })
+            first.status.clone()
+        };
+
+        Ok(Self {
+            minutes_left: estimate_minutes(&status, &batteries),
+            power: batteries.iter().map(|battery| battery.power).sum(),
+            status,
+            capacity: capacity as u8,
+        })
+    }
+
+    // e.g. ", ~1h20m", empty when there is nothing to estimate
+    fn time_estimate(&self) -> String {
+        match self.minutes_left {
+            Some(minutes) if Config::get().battery.show_time_estimate => {
+                format!(", {}", format_minutes(minutes))
+            }
+            _ => String::new(),
         }
     }
 }
@@ -63,21 +212,60 @@ impl Uevent<String> for UeventPowerSupply {
             return Err("non power_supply".into());
         }
 
+        // AC adapter or other batteries trigger uevents too
+        let name = ev_utils::get_element_val(&uevent_str, "POWER_SUPPLY_NAME")
+            .or(ev_utils::get_element_val(&uevent_str, "@")
+                .and_then(|devpath| devpath.rsplit_once("/").map(|(_, name)| name.to_owned())));
+
+        if name.is_some_and(|name| !Config::get().battery.target.contains(&name)) {
+            return Err("unrelated power_supply".into());
+        }
+
         // from netlink we only receive notification that battery has changed
         // all info we will read from sysfs
         Self::new()
     }
 }
 
+impl Uevent<String> for PowerSupplyEvent {
+    fn from_bytes(data: &Vec<u8>) -> Result<Self, String> {
+        let uevent_str =
+            String::from_utf8(data.clone()).map_err(|_| String::from("not valid utf8"))?;
+
+        if ev_utils::get_element_val(&uevent_str, "POWER_SUPPLY_TYPE").as_deref() == Some("Mains") {
+            let online = ev_utils::get_element_val(&uevent_str, "POWER_SUPPLY_ONLINE")
+                .ok_or("POWER_SUPPLY_ONLINE missing".to_owned())?;
+
+            return Ok(PowerSupplyEvent::Adapter(online == "1"));
+        }
+
+        UeventPowerSupply::from_bytes(data).map(PowerSupplyEvent::Battery)
+    }
+}
+
+// state of the first mains adapter found, None if there is no adapter
+fn read_adapter_online() -> Option<bool> {
+    fs::read_dir(SYS_CLASS_PATH)
+        .ok()?
+        .flatten()
+        .find(|entry| {
+            fs::read_to_string(entry.path().join("type")).is_ok_and(|kind| kind.trim() == "Mains")
+        })
+        .and_then(|entry| fs::read_to_string(entry.path().join("online")).ok())
+        .map(|online| online.trim() == "1")
+}
+
 impl From<&str> for Status {
     fn from(value: &str) -> Self {
-        if value == "Not charging" {
+        let value = value.trim();
+
+        if value.eq_ignore_ascii_case("Not charging") {
             Status::NotCharging
-        } else if value == "Charging" {
+        } else if value.eq_ignore_ascii_case("Charging") {
             Status::Charging
-        } else if value == "Discharging" {
+        } else if value.eq_ignore_ascii_case("Discharging") {
             Status::Discharging
-        } else if value == "Full" {
+        } else if value.eq_ignore_ascii_case("Full") {
             Status::Full
         } else {
             Status::Unknown(value.into())
@@ -103,83 +291,372 @@ impl ToString for Status {
     }
 }
 
+// capacity / 10 truncates, so e.g. 19% is still in "10" bucket,
+// min-level is a bucket too, so anything above 10 is treated as 10
+fn capacity_level(capacity: u8, min_level: u8) -> u8 {
+    std::cmp::max(
+        std::cmp::min(capacity, 100) / 10,
+        std::cmp::min(min_level, 10),
+    ) * 10
+}
+
+// explicit level-icons entry wins over {level} template
+fn level_icon(config_battery: &Battery, template: &str, level: u8, charging: bool) -> String {
+    match config_battery.level_icons.get(level, charging) {
+        Some(icon) => icon.into(),
+        None => template.replace("{level}", &level.to_string()),
+    }
+}
+
+// lowest not yet fired threshold capacity has dropped to
+fn crossed_threshold(
+    config_battery: &Battery,
+    capacity: u8,
+    fired: &[u8],
+) -> Option<(u8, Threshold)> {
+    config_battery
+        .warn_at
+        .iter()
+        .flat_map(|threshold| {
+            threshold
+                .levels
+                .iter()
+                .map(move |level| (*level, threshold))
+        })
+        .filter(|(level, _)| capacity <= *level && !fired.contains(level))
+        .min_by_key(|(level, _)| *level)
+        .map(|(level, threshold)| (level, threshold.clone()))
+}
+
+fn is_low(config_battery: &Battery, status: &Status, capacity: u8) -> bool {
+    *status == Status::Discharging
+        && config_battery
+            .warn_at
+            .iter()
+            .flat_map(|threshold| threshold.levels.iter())
+            .any(|level| capacity <= *level)
+}
+
+fn run_warn_command(config_battery: &Battery) {
+    let Some(ref command) = config_battery.warn_command else {
+        warn!("battery warn action requires warn-command to be set");
+        return;
+    };
+
+    run_command(command.clone());
+}
+
+// waiting in separate thread, so command can't block the module and won't become a zombie
+fn run_command(command: String) {
+    std::thread::spawn(move || {
+        if let Err(err) = Command::new("sh").arg("-c").arg(&command).status() {
+            error!("failed to run battery command: {err}");
+        }
+    });
+}
+
+pub fn test_notification() -> Notification {
+    let config = Config::get();
+    let config_battery = &config.battery;
+    let mut notif = Notification::new();
+
+    notif
+        .module(Module::Battery)
+        .app_name(&notif::app_name(&config_battery.app_name))
+        .summary("Battery")
+        .body(Status::Discharging.to_string().as_str())
+        .urgency(config_battery.format.urgency())
+        .timeout(config_battery.format.timeout(Timeout::Millis(2500)))
+        .icon_in(
+            &config_battery.icon_path,
+            &config_battery.discharging_icon.replace("{level}", "50"),
+        );
+
+    if config_battery.show_bar {
+        notif.hint(Hint::Progress(50));
+    }
+
+    notif
+}
+
+// every icon routine can show, templates expanded for all levels
+pub fn icons() -> Vec<String> {
+    let config = Config::get();
+    let config_battery = &config.battery;
+    let mut icons = vec![
+        config_battery.full_icon.clone(),
+        config_battery.low_icon.clone(),
+        config_battery.charger_icon.clone(),
+    ];
+
+    for (template, dynamic, charging) in [
+        (
+            &config_battery.charging_icon,
+            config_battery.dynamic_charging_icon,
+            true,
+        ),
+        (
+            &config_battery.discharging_icon,
+            config_battery.dynamic_discharging_icon,
+            false,
+        ),
+    ] {
+        if !dynamic {
+            icons.push(template.clone());
+            continue;
+        }
+
+        for capacity in (0..=100).step_by(10) {
+            let level = capacity_level(capacity, config_battery.min_level);
+            icons.push(level_icon(config_battery, template, level, charging));
+        }
+    }
+
+    icons.dedup();
+    icons
+        .iter()
+        .map(|icon| notif::join_icon(&config_battery.icon_path, icon))
+        .collect()
+}
+
 pub fn routine() -> impl crate::Routine {
     || {
-        let mut handle = NetlinkHandle::new().unwrap();
+        let mut handle = NetlinkHandle::new().unwrap().subscribe("power_supply");
         let mut notif = Notification::new();
-        let mut last_status = UeventPowerSupply::new().unwrap().status;
+        let initial = UeventPowerSupply::new().unwrap();
+        let mut last_status = initial.status;
+        let mut last_capacity = initial.capacity;
         let mut poll_timeout = Config::get().battery.poll_timeout;
         let mut full = false;
+        let mut fired = Vec::new(); // thresholds already warned about during current discharge
+        let mut last_online = read_adapter_online();
+        let mut power_samples = VecDeque::with_capacity(TREND_SAMPLES);
+        let mut adapter_notif = Notification::new();
+
+        adapter_notif
+            .module(Module::Battery)
+            .replace_tag("sun-battery-charger");
+
+        notif
+            .module(Module::Battery)
+            .replace_tag("sun-battery")
+            .on_action(|key| {
+                if key == "settings" {
+                    if let Some(ref command) = Config::get().battery.settings_command {
+                        run_command(command.clone());
+                    }
+                }
+            });
 
         loop {
-            let config_battery = Config::get().battery;
+            let config = Config::get();
+            let config_battery = &config.battery;
 
-            if config_battery.off {
+            if config_battery.off || crate::shutting_down() {
                 break;
             }
 
-            notif.summary("Battery").icon(&config_battery.icon_path);
+            let status = last_status.to_string();
+            let capacity = last_capacity.to_string();
+            let values = [
+                ("name", "Battery"),
+                ("status", status.as_str()),
+                ("value", capacity.as_str()),
+                ("capacity", capacity.as_str()),
+            ];
 
-            match handle.read_uevent_msec::<UeventPowerSupply, String>(poll_timeout) {
-                Ok(ev) => {
-                    if ev.status == last_status {
+            notif
+                .app_name(&notif::app_name(&config_battery.app_name))
+                .summary(&config_battery.format.summary("Battery", &values));
+            notif.hints.remove("value");
+
+            let mut refresh = crate::take_refresh(Module::Battery);
+
+            crate::set_busy(Module::Battery, false);
+
+            let res = if refresh {
+                UeventPowerSupply::new()
+                    .map(PowerSupplyEvent::Battery)
+                    .map_err(NetlinkError::Serialize)
+            } else {
+                handle.read_uevent_msec::<PowerSupplyEvent, String>(poll_timeout)
+            };
+
+            crate::set_busy(Module::Battery, true);
+
+            // capacity change is shown same way as status change, unless it's time to warn
+            let res = match res {
+                Err(NetlinkError::Timeout) if config_battery.notify_on_capacity_change => {
+                    match UeventPowerSupply::new() {
+                        Ok(ev)
+                            if ev.status == last_status
+                                && ev.capacity != last_capacity
+                                && matches!(ev.status, Status::Charging | Status::Discharging)
+                                && !is_low(config_battery, &ev.status, ev.capacity) =>
+                        {
+                            refresh = true;
+                            Ok(PowerSupplyEvent::Battery(ev))
+                        }
+                        Ok(_) => Err(NetlinkError::Timeout),
+                        // e.g. secondary battery removed between polls, next poll may succeed
+                        Err(err) => {
+                            warn!("can't read battery: {err}");
+                            Err(NetlinkError::Timeout)
+                        }
+                    }
+                }
+                res => res,
+            };
+
+            match res {
+                Ok(PowerSupplyEvent::Adapter(online)) => {
+                    // adapter may send several uevents per plug, only transitions are shown
+                    if last_online == Some(online) {
+                        continue;
+                    }
+
+                    last_online = Some(online);
+
+                    if config_battery.notify_charger {
+                        let body = if online {
+                            "Charger connected"
+                        } else {
+                            "Charger disconnected"
+                        };
+
+                        adapter_notif
+                            .app_name(&notif::app_name(&config_battery.app_name))
+                            .summary(&config_battery.format.summary("Battery", &values))
+                            .body(&config_battery.format.body(body, &values))
+                            .urgency(config_battery.format.urgency())
+                            .timeout(config_battery.format.timeout(Timeout::Millis(2500)))
+                            .icon_in(&config_battery.icon_path, &config_battery.charger_icon)
+                            .show();
+                    }
+                }
+                Ok(PowerSupplyEvent::Battery(ev)) => {
+                    if ev.status == last_status && !refresh {
                         continue;
                     }
 
+                    // draw while charging has nothing to do with draw while discharging
+                    if ev.status != last_status {
+                        power_samples.clear();
+                    }
+
+                    let time_estimate = ev.time_estimate()
+                        + &trend_suffix(config_battery, &mut power_samples, ev.power);
+
+                    if ev.status != Status::Discharging {
+                        fired.clear();
+                        notif::report_low_battery(Module::Battery, None);
+                    }
+
                     full = false;
                     poll_timeout = config_battery.poll_timeout;
                     last_status = ev.status;
+                    last_capacity = ev.capacity;
 
                     notif.hints.clear(); // prevents from setting multiple urgencies
+                    notif.actions(Vec::new());
+                    let status = last_status.to_string();
+                    let capacity = ev.capacity.to_string();
+                    let values = [
+                        ("name", "Battery"),
+                        ("status", status.as_str()),
+                        ("value", capacity.as_str()),
+                        ("capacity", capacity.as_str()),
+                    ];
+                    let body = render_template(
+                        &config_battery.status_template,
+                        &[
+                            ("status", &status),
+                            ("capacity", &capacity),
+                            ("time", &time_estimate),
+                        ],
+                    );
+
                     notif
-                        .urgency(Urgency::Normal)
-                        .body(last_status.to_string().as_str())
-                        .timeout(Timeout::Millis(2500));
+                        .urgency(config_battery.format.urgency())
+                        .summary(&config_battery.format.summary("Battery", &values))
+                        .body(&config_battery.format.body(&body, &values))
+                        .timeout(config_battery.format.timeout(Timeout::Millis(2500)));
+
+                    if config_battery.show_bar {
+                        notif.hint(Hint::Progress(ev.capacity as i32));
+                    }
 
-                    let level = format!("{}0", std::cmp::max(ev.capacity / 10, 1));
+                    let level = capacity_level(ev.capacity, config_battery.min_level);
                     let icon = match last_status {
                         Status::Discharging => {
                             if config_battery.dynamic_discharging_icon {
-                                config_battery.discharging_icon.replace("{level}", &level)
+                                level_icon(
+                                    config_battery,
+                                    &config_battery.discharging_icon,
+                                    level,
+                                    false,
+                                )
                             } else {
-                                config_battery.discharging_icon
+                                config_battery.discharging_icon.clone()
                             }
                         }
                         Status::NotCharging | Status::Charging => {
                             if config_battery.dynamic_charging_icon {
-                                config_battery.charging_icon.replace("{level}", &level)
+                                level_icon(
+                                    config_battery,
+                                    &config_battery.charging_icon,
+                                    level,
+                                    true,
+                                )
                             } else {
-                                config_battery.charging_icon
+                                config_battery.charging_icon.clone()
                             }
                         }
                         Status::Full => {
                             full = true;
                             poll_timeout = -1; // wait for uevent, no need to poll for now
-                            config_battery.full_icon
+                            config_battery.full_icon.clone()
                         }
                         Status::Unknown(ref status) => {
-                            println!("unknown battery status: {status}");
+                            warn!("unknown battery status: {status}");
                             continue;
                         }
                     };
 
-                    notif.icon += &icon;
+                    notif.icon_in(&config_battery.icon_path, &icon);
                     notif.show();
                 }
                 Err(NetlinkError::Timeout) => {
                     let uevent = UeventPowerSupply::new().unwrap();
+                    let trend = trend_suffix(config_battery, &mut power_samples, uevent.power);
 
                     notif
                         .body(last_status.to_string().as_str())
                         .timeout(Timeout::Never);
 
+                    if config_battery.show_bar {
+                        notif.hint(Hint::Progress(uevent.capacity as i32));
+                    }
+
                     if !full && uevent.status == Status::Full {
                         full = true;
                         poll_timeout = -1; // wait for uevent, no need to poll for now
 
-                        notif.urgency(Urgency::Normal);
-                        notif.body("Battery is full");
-                        notif.icon += &config_battery.full_icon;
+                        notif
+                            .actions(Vec::new())
+                            .urgency(config_battery.format.urgency())
+                            .timeout(config_battery.format.timeout(Timeout::Never));
+                        let body = render_template(
+                            &config_battery.full_template,
+                            &[
+                                ("status", &Status::Full.to_string()),
+                                ("capacity", &uevent.capacity.to_string()),
+                                ("time", ""),
+                            ],
+                        );
+
+                        notif.body(&config_battery.format.body(&body, &values));
+                        notif.icon_in(&config_battery.icon_path, &config_battery.full_icon);
                         notif.show();
 
                         continue;
@@ -187,17 +664,98 @@ pub fn routine() -> impl crate::Routine {
 
                     let cap = uevent.capacity;
 
-                    if uevent.status == Status::Discharging && cap <= config_battery.warn_at {
-                        notif.urgency(Urgency::Critical);
-                        notif.body(format!("{cap}% left, connect charger").as_str());
-                        notif.icon += &config_battery.low_icon;
-                        notif.show();
+                    if uevent.status != Status::Discharging {
+                        fired.clear();
+                        notif::report_low_battery(Module::Battery, None);
+                        continue;
+                    }
+
+                    let low = is_low(config_battery, &uevent.status, cap);
+                    let aggregated =
+                        notif::report_low_battery(Module::Battery, low.then_some(("Laptop", cap)));
+
+                    // each threshold fires once per discharge, skipped ones (e.g. after suspend)
+                    // are marked too, so only the most severe is shown
+                    if let Some((level, threshold)) = crossed_threshold(config_battery, cap, &fired)
+                    {
+                        let action = config_battery.warn_action;
+
+                        fired.extend(
+                            config_battery
+                                .warn_at
+                                .iter()
+                                .flat_map(|threshold| threshold.levels.iter())
+                                .filter(|other| **other >= level),
+                        );
+
+                        if matches!(action, WarnAction::Notify | WarnAction::Both) && !aggregated {
+                            if config_battery.settings_command.is_some() {
+                                notif.actions(vec![("settings".into(), "Power settings".into())]);
+                            }
+
+                            notif
+                                .urgency(threshold.urgency.into())
+                                .timeout(Timeout::from(threshold.timeout));
+                            notif.body(&render_template(
+                                &config_battery.low_template,
+                                &[
+                                    ("status", &uevent.status.to_string()),
+                                    ("capacity", &cap.to_string()),
+                                    ("time", &(uevent.time_estimate() + &trend)),
+                                ],
+                            ));
+                            notif.icon_in(&config_battery.icon_path, &config_battery.low_icon);
+                            notif.show();
+                        }
+
+                        if matches!(action, WarnAction::Command | WarnAction::Both) {
+                            run_warn_command(config_battery);
+                        }
                     }
                 }
                 Err(NetlinkError::IO(ErrorKind::Interrupted)) => (),
+                Err(err) if err.is_recoverable() => {
+                    warn!("netlink socket overrun or reset, reconnecting");
+                    handle.reconnect().unwrap_or_else(|err| panic!("{err}"));
+                }
                 Err(NetlinkError::IO(kind)) => panic!("{kind:?}"),
                 Err(_) => (),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_ignores_case_and_whitespace() {
+        assert_eq!(Status::from("Charging"), Status::Charging);
+        assert_eq!(Status::from("charging"), Status::Charging);
+        assert_eq!(Status::from("CHARGING\n"), Status::Charging);
+        assert_eq!(Status::from(" Discharging "), Status::Discharging);
+        assert_eq!(Status::from("not charging"), Status::NotCharging);
+        assert_eq!(Status::from("FULL"), Status::Full);
+        assert_eq!(Status::from(" Weird \n"), Status::Unknown("Weird".into()));
+    }
+
+    #[test]
+    fn capacity_level_buckets() {
+        assert_eq!(capacity_level(0, 1), 10);
+        assert_eq!(capacity_level(5, 1), 10);
+        assert_eq!(capacity_level(95, 1), 90);
+        assert_eq!(capacity_level(100, 1), 100);
+
+        assert_eq!(capacity_level(0, 0), 0);
+        assert_eq!(capacity_level(5, 0), 0);
+        assert_eq!(capacity_level(95, 0), 90);
+        assert_eq!(capacity_level(100, 0), 100);
+    }
+
+    #[test]
+    fn capacity_level_clamps_min_level() {
+        assert_eq!(capacity_level(5, 20), 100);
+        assert_eq!(capacity_level(5, u8::MAX), 100);
+    }
+}
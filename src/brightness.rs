@@ -1,85 +1,415 @@
-use crate::config::Config;
+use crate::config::{Brightness, Config};
 use crate::netlink::utils as ev_utils;
-use crate::netlink::{NetlinkError, NetlinkHandle, Uevent};
-use crate::notif::{Hint, Notification, Timeout};
+use crate::netlink::{NetlinkError, NetlinkHandle, UeventFields};
+use crate::notif::{self, Hint, Notification, Timeout};
+use crate::Module;
 use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Kind {
+    Screen,
+    Keyboard,
+}
 
 struct UeventBacklight {
     devpath: String,
+    kind: Kind,
+}
+
+impl UeventBacklight {
+    // None for leds other than keyboard backlight
+    fn from_fields(fields: &UeventFields) -> Option<Self> {
+        let devpath = fields.get("@")?;
+        let kind = match fields.get("SUBSYSTEM")?.as_str() {
+            "backlight" => Kind::Screen,
+            "leds" if devpath.ends_with("::kbd_backlight") => Kind::Keyboard,
+            _ => return None,
+        };
+
+        Some(Self { devpath, kind })
+    }
+
+    // None when device is already gone, e.g. unplugged keyboard
+    fn get_brightness(&self) -> Option<u32> {
+        read_brightness(Path::new(&format!("/sys{}", self.devpath)))
+    }
 }
 
-impl Uevent<String> for UeventBacklight {
-    fn from_bytes(data: &Vec<u8>) -> Result<Self, String> {
-        let uevent_str =
-            String::from_utf8(data.clone()).map_err(|_| String::from("invalid utf8"))?;
+fn read_sys_val(device: &Path, name: &str) -> Option<f32> {
+    let val = std::fs::read_to_string(device.join(name)).ok()?;
+
+    f32::from_str(val.trim()).ok()
+}
 
-        if !uevent_str.contains("SUBSYSTEM=backlight") {
-            return Err("non backlight".into());
+fn read_brightness(device: &Path) -> Option<u32> {
+    Some(
+        (read_sys_val(device, "brightness")? / read_sys_val(device, "max_brightness")? * 100.)
+            as u32,
+    )
+}
+
+// where brightness of a channel is read from
+enum Source {
+    Sysfs(PathBuf),
+    // external monitor over DDC/CI (VCP feature 0x10), via ddcutil, display number if set
+    Ddc(Option<u32>),
+}
+
+impl Source {
+    fn read(&self) -> Option<u32> {
+        match self {
+            Source::Sysfs(device) => read_brightness(device),
+            Source::Ddc(display) => read_ddc_brightness(*display),
         }
+    }
 
-        Ok(Self {
-            devpath: ev_utils::get_element_val(&uevent_str, "@")
-                .ok_or("devpath not found".to_owned())?,
-        })
+    // uevents of other devices (secondary panel, external keyboard) are not ours to show
+    fn owns(&self, devpath: &str) -> bool {
+        match self {
+            Source::Sysfs(device) => device
+                .file_name()
+                .is_some_and(|name| devpath.rsplit_once("/").unwrap().1 == name),
+            Source::Ddc(_) => false,
+        }
     }
 }
 
-impl UeventBacklight {
-    fn get_sys_val(&self, name: &str) -> f32 {
-        let val = std::fs::read(format!("/sys{}/{}", self.devpath, name))
-            .unwrap()
-            .iter()
-            .take_while(|b| **b != b'\n')
-            .map(|b| *b as char)
-            .collect::<String>();
+// "VCP 10 C 50 100", current and max value
+fn read_ddc_brightness(display: Option<u32>) -> Option<u32> {
+    let mut command = Command::new("ddcutil");
+
+    command.args(["getvcp", "10", "--brief"]);
 
-        f32::from_str(&val).unwrap()
+    if let Some(display) = display {
+        command.args(["--display", &display.to_string()]);
     }
 
-    fn get_brightness(&self) -> u32 {
-        (self.get_sys_val("brightness") / self.get_sys_val("max_brightness") * 100.) as u32
+    let output = match command.output() {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            debug!(
+                "ddcutil failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return None;
+        }
+        Err(err) => {
+            debug!("ddcutil failed: {err}");
+            return None;
+        }
+    };
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut fields = output.split_whitespace().skip(3);
+    let current = f32::from_str(fields.next()?).ok()?;
+    let max = f32::from_str(fields.next()?).ok()?;
+
+    (max > 0.).then(|| (current / max * 100.) as u32)
+}
+
+pub fn test_notification() -> Notification {
+    let config = Config::get();
+    let brightness_config = &config.brightness;
+    let mut notif = Notification::new();
+
+    notif
+        .module(Module::Brightness)
+        .app_name(&notif::app_name(&brightness_config.app_name))
+        .summary("Brightness")
+        .icon_in(&brightness_config.icon_path, &brightness_config.icon)
+        .urgency(brightness_config.format.urgency())
+        .timeout(brightness_config.format.timeout(Timeout::Millis(3000)))
+        .hint(Hint::Progress(50));
+
+    notif
+}
+
+pub fn icons() -> Vec<String> {
+    let config = Config::get();
+    let brightness_config = &config.brightness;
+
+    [&brightness_config.icon, &brightness_config.keyboard_icon]
+        .iter()
+        .map(|icon| notif::join_icon(&brightness_config.icon_path, icon))
+        .collect()
+}
+
+// screen and keyboard backlight are tracked separately, so they don't clobber each other
+struct Channel {
+    kind: Kind,
+    source: Source,
+    notif: Notification,
+    last_brightness: u32,
+    shown_brightness: Option<u32>,
+    shown_at: Option<Instant>,
+    pending: bool,
+    polled_at: Instant,
+}
+
+impl Channel {
+    fn new(kind: Kind, source: Source, last_brightness: u32) -> Self {
+        let mut notif = Notification::new();
+        let tag = match kind {
+            Kind::Screen => "sun-brightness",
+            Kind::Keyboard => "sun-keyboard-backlight",
+        };
+
+        notif.module(Module::Brightness).replace_tag(tag);
+
+        Self {
+            kind,
+            source,
+            notif,
+            last_brightness,
+            shown_brightness: None,
+            shown_at: None,
+            pending: false,
+            polled_at: Instant::now(),
+        }
+    }
+
+    // ms, 0 when it isn't polled, ddcutil is slow and busy monitor may stop answering,
+    // so ddc-poll-ms is a floor even when poll-ms is smaller
+    fn poll_interval(&self, brightness_config: &Brightness) -> u64 {
+        match self.source {
+            Source::Sysfs(_) => brightness_config.poll_ms,
+            Source::Ddc(_) => {
+                std::cmp::max(brightness_config.poll_ms, brightness_config.ddc_poll_ms)
+            }
+        }
     }
+
+    fn show(&mut self, brightness_config: &Brightness) {
+        let (summary, icon) = match self.kind {
+            Kind::Screen => ("Brightness", &brightness_config.icon),
+            Kind::Keyboard => ("Keyboard backlight", &brightness_config.keyboard_icon),
+        };
+
+        let values = [
+            ("name", summary),
+            ("value", &self.last_brightness.to_string()),
+        ];
+
+        self.notif
+            .app_name(&notif::app_name(&brightness_config.app_name))
+            .summary(&brightness_config.format.summary(summary, &values))
+            .body(&brightness_config.format.body("", &values))
+            .urgency(brightness_config.format.urgency())
+            .icon_in(&brightness_config.icon_path, icon)
+            .timeout(brightness_config.format.timeout(Timeout::Millis(3000)))
+            .transient(brightness_config.transient)
+            .suppress_sound(brightness_config.suppress_sound)
+            .hint(Hint::Progress(self.last_brightness as i32));
+        self.notif.show();
+
+        self.shown_brightness = Some(self.last_brightness);
+        self.shown_at = Some(Instant::now());
+    }
+}
+
+fn find_device(kind: Kind, target: Option<&str>) -> Option<PathBuf> {
+    let (class, suffix) = match kind {
+        Kind::Screen => ("backlight", ""),
+        Kind::Keyboard => ("leds", "::kbd_backlight"),
+    };
+
+    let devices = ev_utils::sysfs_devices(class)
+        .into_iter()
+        .filter(|device| {
+            let name = device
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+
+            name.ends_with(suffix) && target.is_none_or(|target| name == target)
+        })
+        .collect::<Vec<_>>();
+
+    // e.g. intel_backlight and nvidia_0 on hybrid laptops, finest grained one is usually the panel,
+    // devices are sorted, so first one wins a tie on every run
+    devices
+        .into_iter()
+        .rev()
+        .max_by_key(|device| read_sys_val(device, "max_brightness").map_or(0, |max| max as u64))
+}
+
+// everything channels are found by
+fn targets(brightness_config: &Brightness) -> (Option<String>, Option<String>, bool, Option<u32>) {
+    (
+        brightness_config.target.clone(),
+        brightness_config.keyboard_target.clone(),
+        brightness_config.ddc,
+        brightness_config.ddc_display,
+    )
+}
+
+fn find_channels(brightness_config: &Brightness) -> Vec<Channel> {
+    [
+        (Kind::Screen, brightness_config.target.as_deref()),
+        (Kind::Keyboard, brightness_config.keyboard_target.as_deref()),
+    ]
+    .into_iter()
+    .filter_map(|(kind, target)| {
+        let source = match find_device(kind, target) {
+            Some(device) => Source::Sysfs(device),
+            // external monitors have no backlight device
+            None if kind == Kind::Screen && brightness_config.ddc => {
+                Source::Ddc(brightness_config.ddc_display)
+            }
+            None => return None,
+        };
+        let brightness = source.read()?;
+
+        Some(Channel::new(kind, source, brightness))
+    })
+    .collect()
 }
 
 pub fn routine() -> impl crate::Routine {
     || {
-        let mut last_brightness = 0; // TODO: replace with actual value
-        let mut handle = NetlinkHandle::new().unwrap();
-        let mut notif = Notification::new();
+        let config = Config::get();
+        let mut applied_targets = targets(&config.brightness);
+        let mut channels = find_channels(&config.brightness);
+
+        if channels.is_empty() {
+            info!("no backlight device found, brightness module is not running");
+            return;
+        }
+
+        let mut handle = NetlinkHandle::new()
+            .unwrap()
+            .subscribe("backlight")
+            .subscribe("leds");
 
         loop {
-            let brightness_config = Config::get().brightness;
+            let config = Config::get();
+            let brightness_config = &config.brightness;
+            let cooldown = Duration::from_millis(brightness_config.cooldown);
+
+            // pinned device or ddc changed on reload, other devices' uevents are ignored from now on
+            if targets(brightness_config) != applied_targets {
+                applied_targets = targets(brightness_config);
+                channels = find_channels(brightness_config);
 
-            if brightness_config.off {
+                if channels.is_empty() {
+                    warn!("no backlight device matches target, brightness module is not running");
+                    break;
+                }
+            }
+
+            // monitor doesn't tell about changes, it has to be asked
+            let ddc = channels
+                .iter()
+                .any(|channel| matches!(channel.source, Source::Ddc(_)));
+            let poll_ms = match brightness_config.poll_ms {
+                0 if ddc => brightness_config.ddc_poll_ms,
+                poll_ms => poll_ms,
+            };
+
+            if brightness_config.off || crate::shutting_down() {
                 break;
             }
 
-            match handle.read_uevent::<UeventBacklight, String>() {
-                Ok(ev) => {
-                    if last_brightness == ev.get_brightness() {
+            // nothing to show until first change is seen
+            if crate::take_refresh(Module::Brightness) {
+                for channel in channels.iter_mut() {
+                    if channel.shown_brightness.is_some() {
+                        channel.show(brightness_config);
+                    }
+                }
+            }
+
+            crate::set_busy(Module::Brightness, false);
+
+            // while burst of changes is going, wait for it to settle
+            let pending = channels.iter().any(|channel| channel.pending);
+            let res = match (pending, poll_ms) {
+                (true, 0) => handle.next_event(brightness_config.cooldown as i32),
+                (true, poll_ms) => {
+                    handle.next_event(std::cmp::min(brightness_config.cooldown, poll_ms) as i32)
+                }
+                (false, 0) => handle.next_event(-1),
+                (false, poll_ms) => handle.next_event(poll_ms as i32),
+            };
+
+            crate::set_busy(Module::Brightness, true);
+
+            match res {
+                Ok(fields) => {
+                    let Some(ev) = UeventBacklight::from_fields(&fields) else {
+                        continue;
+                    };
+                    let Some(channel) = channels.iter_mut().find(|channel| {
+                        channel.kind == ev.kind && channel.source.owns(&ev.devpath)
+                    }) else {
+                        continue;
+                    };
+
+                    if ev.kind == Kind::Keyboard && brightness_config.keyboard_off {
                         continue;
                     }
 
-                    if let Some(ref target) = brightness_config.target {
-                        if ev.devpath.rsplit_once("/").unwrap().1 != target {
-                            continue;
+                    let Some(brightness) = ev.get_brightness() else {
+                        continue;
+                    };
+
+                    if channel.last_brightness == brightness {
+                        continue;
+                    }
+
+                    channel.last_brightness = brightness;
+
+                    if channel.shown_at.is_some_and(|at| at.elapsed() < cooldown) {
+                        channel.pending = true;
+                        continue;
+                    }
+
+                    channel.show(brightness_config);
+                }
+                Err(NetlinkError::Timeout) => {
+                    // final value of the burst, stray identical ones are ignored
+                    for channel in channels.iter_mut().filter(|channel| channel.pending) {
+                        channel.pending = false;
+
+                        if channel.shown_brightness != Some(channel.last_brightness) {
+                            channel.show(brightness_config);
                         }
                     }
 
-                    last_brightness = ev.get_brightness();
-
-                    notif
-                        .summary("Brightness")
-                        .icon(&format!(
-                            "{}{}",
-                            brightness_config.icon_path, brightness_config.icon
-                        ))
-                        .timeout(Timeout::Millis(3000))
-                        .hint(Hint::Value(last_brightness as i32));
-                    notif.show();
+                    // for drivers which don't send uevents on change
+                    for channel in channels.iter_mut() {
+                        if channel.kind == Kind::Keyboard && brightness_config.keyboard_off {
+                            continue;
+                        }
+
+                        let interval = channel.poll_interval(brightness_config);
+
+                        if interval == 0
+                            || channel.polled_at.elapsed() < Duration::from_millis(interval)
+                        {
+                            continue;
+                        }
+
+                        channel.polled_at = Instant::now();
+
+                        let Some(brightness) = channel.source.read() else {
+                            continue;
+                        };
+
+                        if channel.last_brightness != brightness {
+                            channel.last_brightness = brightness;
+                            channel.show(brightness_config);
+                        }
+                    }
                 }
                 Err(NetlinkError::IO(ErrorKind::Interrupted)) => (),
+                Err(err) if err.is_recoverable() => {
+                    warn!("netlink socket overrun or reset, reconnecting");
+                    handle.reconnect().unwrap_or_else(|err| panic!("{err}"));
+                }
                 Err(NetlinkError::IO(kind)) => panic!("{kind:?}"),
                 Err(_) => (),
             }
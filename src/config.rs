@@ -1,14 +1,22 @@
+use crate::notif;
 use crate::Message;
-use inotify::{EventMask, Inotify, WatchMask};
+use inotify::{EventMask, Events, Inotify, WatchMask};
 use knuffel;
-use knuffel::errors::Error as KnuffelError;
-use std::io::ErrorKind;
+use knuffel::ast::{Literal, SpannedNode};
+use knuffel::errors::{DecodeError, Error as KnuffelError};
+use knuffel::span::Span;
+use miette::{Diagnostic, SourceSpan};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
-use std::sync::RwLock;
+use std::sync::{Arc, OnceLock, RwLock};
 
 const CONFIG_FILE: &'static str = "config.kdl";
 
 const DEFAULT_ICON_PATH: &'static str = "/usr/share/icons/Adwaita/symbolic/";
+const DEFAULT_FULLCOLOR_ICON_PATH: &'static str = "/usr/share/icons/Adwaita/scalable/";
 const DEFAULT_ERROR_ICON: &'static str =
     "/usr/share/icons/Adwaita/symbolic/status/computer-fail-symbolic.svg";
 
@@ -16,26 +24,663 @@ const DEFAULT_SINK_ICON: &'static str = "status/audio-volume-high-symbolic.svg";
 const DEFAULT_SINK_MUTED_ICON: &'static str = "status/audio-volume-muted-symbolic.svg";
 const DEFAULT_SINK_BLUETOOTH_ICON: &'static str = "status/audio-volume-high-symbolic.svg";
 
+const DEFAULT_NETWORK_CONNECTED_ICON: &'static str = "status/network-wired-symbolic.svg";
+const DEFAULT_NETWORK_DISCONNECTED_ICON: &'static str =
+    "status/network-wired-disconnected-symbolic.svg";
+const DEFAULT_THERMAL_ICON: &'static str = "status/dialog-warning-symbolic.svg";
+const DEFAULT_LID_ICON: &'static str = "devices/computer-symbolic.svg";
+
+const DEFAULT_MUTED_SUFFIX: &'static str = " muted";
+
 const DEFAULT_SOURCE_ICON: &'static str = "status/microphone-sensitivity-high-symbolic.svg";
 const DEFAULT_SOURCE_MUTED_ICON: &'static str = "status/microphone-sensitivity-muted-symbolic.svg";
 
-const DEFAULT_KEYBOARD_ICON: &'static str = "devices/input-keyboard-symbolic.svg";
+pub const DEFAULT_KEYBOARD_ICON: &'static str = "devices/input-keyboard-symbolic.svg";
 
+const DEFAULT_KEYBOARD_BACKLIGHT_ICON: &'static str = "status/keyboard-brightness-symbolic.svg";
 const DEFAULT_BRIGHTNESS_ICON: &'static str = "status/display-brightness-symbolic.svg";
 
 const DEFAULT_BATTERY_TARGET: &'static str = "BAT0";
+const DEFAULT_BATTERY_STATUS_TEMPLATE: &'static str = "{status}{time}";
+const DEFAULT_BATTERY_LOW_TEMPLATE: &'static str = "{capacity}% left{time}, connect charger";
+const DEFAULT_BATTERY_FULL_TEMPLATE: &'static str = "Battery is full";
+const DEFAULT_BATTERY_CHARGER_ICON: &'static str = "devices/ac-adapter-symbolic.svg";
 const DEFAULT_BATTERY_FULL_ICON: &'static str = "status/battery-level-100-charged-symbolic.svg";
 const DEFAULT_BATTERY_LOW_ICON: &'static str = "status/battery-caution-symbolic.svg";
 const DEFAULT_BATTERY_CHARGING_ICON: &'static str =
     "status/battery-level-{level}-charging-symbolic.svg";
 const DEFAULT_BATTERY_DISCHARGING_ICON: &'static str = "status/battery-level-{level}-symbolic.svg";
 
-static CONFIG: RwLock<Option<Config>> = RwLock::new(None);
+// shared, so getting config doesn't clone it
+static CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+static SOURCE: OnceLock<Source> = OnceLock::new();
+
+pub enum Source {
+    File(PathBuf),
+    Stdin(String),
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Stdin(_) => write!(f, "<stdin>"),
+        }
+    }
+}
+
+impl Source {
+    pub fn stdin() -> std::io::Result<Self> {
+        let mut text = String::new();
+
+        std::io::stdin().read_to_string(&mut text)?;
+
+        Ok(Source::Stdin(text))
+    }
+
+    // must be called before first config update, otherwise default source is used
+    pub fn set(self) {
+        if SOURCE.set(self).is_err() {
+            panic!("config source is already set");
+        }
+    }
+
+    pub fn get() -> &'static Self {
+        SOURCE.get_or_init(|| Source::File(resolve_path()))
+    }
+
+    // missing file is the same as built-in config
+    pub fn text(&self) -> String {
+        match self {
+            Source::File(path) => {
+                std::fs::read_to_string(path).unwrap_or(include_str!("../config.kdl").into())
+            }
+            Source::Stdin(text) => text.clone(),
+        }
+    }
+}
+
+// every field with its default value, so users have something to start from
+pub fn default_kdl() -> String {
+    let defaults = serde_json::to_value(Config::fallback()).unwrap();
+    let mut kdl = String::from("// SUN config, every value below is the default one\n");
+
+    write_kdl_nodes(&mut kdl, &defaults, 0);
+    kdl
+}
+
+fn module_comment(name: &str) -> Option<&'static str> {
+    match name {
+        "sound" => Some("volume of default sink (output) and source (mic), libpulse"),
+        "battery" => Some("power_supply uevents, low battery warnings"),
+        "brightness" => Some("screen and keyboard backlight uevents"),
+        "keyboard" => Some("layout switches via niri, sway, hyprland or X11"),
+        "thermal" => Some("thermal zone temperatures, warnings when they get too hot"),
+        "network" => Some("interfaces going up/down and (dis)connecting, rtnetlink"),
+        "lid" => Some("laptop lid opened/closed, evdev lid switch or /proc/acpi"),
+        "lock-keys" => Some("caps/num/scroll lock from keyboard leds, no X11 or compositor needed"),
+        _ => None,
+    }
+}
+
+fn kdl_value(value: &serde_json::Value) -> String {
+    match value {
+        // json escapes are valid kdl ones
+        serde_json::Value::String(text) => serde_json::to_string(text).unwrap(),
+        other => other.to_string(),
+    }
+}
+
+fn write_kdl_nodes(kdl: &mut String, node: &serde_json::Value, depth: usize) {
+    use serde_json::Value;
+
+    let indent = "    ".repeat(depth);
+    let Value::Object(fields) = node else {
+        return;
+    };
+
+    for (name, value) in fields {
+        match (name.as_str(), value) {
+            // presence flags can't take false
+            (name, Value::Bool(false)) if name == "off" || name.ends_with("-off") => {
+                kdl.push_str(&format!("{indent}// {name}\n"));
+            }
+            (name, Value::Null) => kdl.push_str(&format!("{indent}// {name} \"...\"\n")),
+            ("layout-names", value) => {
+                let names = value["names"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|layout| {
+                        format!(
+                            " {} {};",
+                            kdl_value(&layout["raw"]),
+                            kdl_value(&layout["display"])
+                        )
+                    })
+                    .collect::<String>();
+
+                kdl.push_str(&format!(
+                    "{indent}// {name} {{ \"English (US)\" \"EN\"; }}\n"
+                ));
+                kdl.push_str(&format!("{indent}{name} {{{names} }}\n"));
+            }
+            ("interface-icons", value) => {
+                let icons = value["icons"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|icon| {
+                        format!(
+                            " {} {} {};",
+                            kdl_value(&icon["interface"]),
+                            kdl_value(&icon["connected"]),
+                            kdl_value(&icon["disconnected"])
+                        )
+                    })
+                    .collect::<String>();
+
+                kdl.push_str(&format!(
+                    "{indent}// {name} {{ \"wlan0\" \"wireless.svg\" \"wireless-offline.svg\"; }}\n"
+                ));
+                kdl.push_str(&format!("{indent}{name} {{{icons} }}\n"));
+            }
+            ("level-icons", value) => {
+                let icons = value["icons"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|icon| {
+                        format!(
+                            " level {} {} charging={};",
+                            icon["level"],
+                            kdl_value(&icon["icon"]),
+                            icon["charging"]
+                        )
+                    })
+                    .collect::<String>();
+
+                kdl.push_str(&format!(
+                    "{indent}// {name} {{ level 70 \"bat-70.svg\"; }}\n"
+                ));
+                kdl.push_str(&format!("{indent}{name} {{{icons} }}\n"));
+            }
+            ("urgency-format", value) => {
+                kdl.push_str(&format!(
+                    "{indent}// {name} {{ critical icon-suffix=\"-alert\" {{ hint \"fgcolor\" \"#ff0000\"; }}; }}\n"
+                ));
+                kdl.push_str(&format!("{indent}{name} {{\n"));
+
+                for (urgency, format) in value.as_object().into_iter().flatten() {
+                    let suffix = format["icon-suffix"]
+                        .as_str()
+                        .map(|suffix| format!(" icon-suffix={}", kdl_value(&suffix.into())))
+                        .unwrap_or_default();
+                    let hints = format["hints"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .map(|hint| {
+                            format!(
+                                " hint {} {};",
+                                kdl_value(&hint["name"]),
+                                kdl_value(&hint["value"])
+                            )
+                        })
+                        .collect::<String>();
+
+                    kdl.push_str(&format!("{indent}    {urgency}{suffix} {{{hints} }}\n"));
+                }
+
+                kdl.push_str(&format!("{indent}}}\n"));
+            }
+            (name, Value::Array(items)) if items.is_empty() => {
+                kdl.push_str(&format!("{indent}// {name} \"...\"\n"));
+            }
+            // warn-at 15 urgency="critical" timeout=0
+            (name, Value::Array(items)) if items.iter().all(Value::is_object) => {
+                for item in items {
+                    let args = item["levels"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .map(|level| format!(" {level}"))
+                        .collect::<String>();
+                    let props = item
+                        .as_object()
+                        .into_iter()
+                        .flatten()
+                        .filter(|(key, _)| *key != "levels")
+                        .map(|(key, val)| format!(" {key}={}", kdl_value(val)))
+                        .collect::<String>();
+
+                    kdl.push_str(&format!("{indent}{name}{args}{props}\n"));
+                }
+            }
+            (name, Value::Array(items)) => {
+                let args = items
+                    .iter()
+                    .map(|item| format!(" {}", kdl_value(item)))
+                    .collect::<String>();
+
+                kdl.push_str(&format!("{indent}{name}{args}\n"));
+            }
+            (name, Value::Object(_)) => {
+                kdl.push('\n');
+
+                if let Some(comment) = module_comment(name).filter(|_| depth == 0) {
+                    kdl.push_str(&format!("{indent}// {comment}\n"));
+                }
+
+                kdl.push_str(&format!("{indent}{name} {{\n"));
+                write_kdl_nodes(kdl, value, depth + 1);
+                kdl.push_str(&format!("{indent}}}\n"));
+            }
+            (name, value) => kdl.push_str(&format!("{indent}{name} {}\n", kdl_value(value))),
+        }
+    }
+}
+
+// included files go first and including file overrides them: scalars are replaced, sections
+// are merged child by child (all children with the same name are replaced together)
+fn expand_includes(
+    name: &str,
+    text: &str,
+    path: Option<&Path>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<String, KnuffelError> {
+    // errors are reported against the file they are in, not the merged text
+    knuffel::parse::<Config>(name, text)?;
+
+    let ast = knuffel::parse_ast::<Span>(name, text)?;
+
+    if !ast.nodes.iter().any(|node| &**node.node_name == "include") {
+        return Ok(text.into());
+    }
+
+    let dir = path.and_then(Path::parent).unwrap_or(Path::new(""));
+    let mut merged = Vec::new();
+    let mut errors = Vec::new();
+
+    for node in ast
+        .nodes
+        .iter()
+        .filter(|node| &**node.node_name == "include")
+    {
+        let argument = &node.arguments[0].literal;
+        let Literal::String(ref include) = **argument else {
+            continue;
+        };
+        let include_path = dir.join(&**include);
+        let include_error = |message: String| DecodeError::Conversion {
+            span: argument.span().clone(),
+            source: message.into(),
+        };
+
+        let canonical = match include_path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(err) => {
+                errors.push(include_error(format!("can't include {include}: {err}")));
+                continue;
+            }
+        };
+
+        if stack.contains(&canonical) {
+            errors.push(include_error(format!(
+                "{include} is already being included"
+            )));
+            continue;
+        }
+
+        let include_text = match std::fs::read_to_string(&include_path) {
+            Ok(include_text) => include_text,
+            Err(err) => {
+                errors.push(include_error(format!("can't include {include}: {err}")));
+                continue;
+            }
+        };
+        let include_name = include_path.display().to_string();
+
+        stack.push(canonical);
+        let expanded = expand_includes(&include_name, &include_text, Some(&include_path), stack);
+        stack.pop();
+
+        let expanded = expanded?;
+        let include_ast = knuffel::parse_ast::<Span>(&include_name, &expanded)?;
+
+        merge_nodes(&mut merged, &expanded, &include_ast.nodes);
+    }
+
+    if !errors.is_empty() {
+        let err = knuffel::parse_with_context::<Config, Span, _>(name, text, |ctx| {
+            errors.into_iter().for_each(|err| ctx.emit_error(err));
+        });
+
+        return Err(err.unwrap_err());
+    }
+
+    merge_nodes(&mut merged, text, &ast.nodes);
+
+    let merged = merged
+        .into_iter()
+        .map(|node| match node.children {
+            Some(children) => {
+                let children = children
+                    .into_iter()
+                    .map(|(_, child)| format!("    {child}\n"))
+                    .collect::<String>();
+
+                format!("{} {{\n{children}}}\n", node.name)
+            }
+            None => format!("{}\n", node.text),
+        })
+        .collect();
+
+    Ok(merged)
+}
+
+fn merge_nodes(merged: &mut Vec<MergedNode>, text: &str, nodes: &[SpannedNode<Span>]) {
+    let source = |span: &Span| text[span.0..span.1].to_string();
+
+    for node in nodes.iter().filter(|node| &**node.node_name != "include") {
+        let node = MergedNode {
+            name: node.node_name.to_string(),
+            text: source(node.span()),
+            children: node.children.as_ref().map(|children| {
+                children
+                    .iter()
+                    .map(|child| (child.node_name.to_string(), source(child.span())))
+                    .collect()
+            }),
+        };
+
+        match merged.iter_mut().find(|merged| merged.name == node.name) {
+            Some(MergedNode {
+                children: Some(ref mut children),
+                ..
+            }) if node.children.is_some() => {
+                let overrides = node.children.unwrap();
+
+                children.retain(|(name, _)| !overrides.iter().any(|(other, _)| other == name));
+                children.extend(overrides);
+            }
+            Some(existing) => *existing = node,
+            None => merged.push(node),
+        }
+    }
+}
+
+// one "path:line:col: message" line per error, instead of knuffel's debug dump
+pub fn describe_error(err: &KnuffelError) -> String {
+    // error may come from included file, source code of error knows which one
+    let read_span = |span: &SourceSpan| err.source_code()?.read_span(span, 0, 0).ok();
+    let source = read_span(&(0, 0).into())
+        .and_then(|contents| contents.name().map(String::from))
+        .unwrap_or_else(|| Source::get().to_string());
+    let mut lines = Vec::new();
+
+    for diag in err.related().into_iter().flatten() {
+        let label = diag.labels().and_then(|mut labels| labels.next());
+        let contents = label.as_ref().and_then(|label| read_span(label.inner()));
+
+        match (label, contents) {
+            (Some(label), Some(contents)) => {
+                let hint = label.label().map(|hint| format!(" ({hint})"));
+
+                lines.push(format!(
+                    "{source}:{}:{}: {diag}{}",
+                    contents.line() + 1,
+                    contents.column() + 1,
+                    hint.unwrap_or_default()
+                ));
+            }
+            _ => lines.push(format!("{source}: {diag}")),
+        }
+    }
+
+    if lines.is_empty() {
+        lines.push(format!("{source}: {err}"));
+    }
+
+    lines.join("\n")
+}
+
+// SUN_CONFIG, then first existing of $XDG_CONFIG_HOME/sun/, ~/.config/sun/ and
+// working directory, if none exists - the most preferred one
+fn resolve_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("SUN_CONFIG") {
+        return path.into();
+    }
+
+    let candidates = [
+        std::env::var_os("XDG_CONFIG_HOME").map(|dir| PathBuf::from(dir).join("sun")),
+        std::env::var_os("HOME").map(|dir| PathBuf::from(dir).join(".config/sun")),
+        Some(PathBuf::new()),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|dir| dir.join(CONFIG_FILE))
+    .collect::<Vec<_>>();
+
+    candidates
+        .iter()
+        .find(|path| path.exists())
+        .unwrap_or(&candidates[0])
+        .clone()
+}
+
+#[derive(knuffel::DecodeScalar, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IconVariant {
+    #[default]
+    Symbolic,
+    Fullcolor,
+}
+
+#[derive(knuffel::DecodeScalar, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarnAction {
+    #[default]
+    Notify,
+    Command,
+    Both,
+}
+
+#[derive(knuffel::DecodeScalar, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Urgency {
+    Normal,
+    #[default]
+    Critical,
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+pub struct Format {
+    // body is sent as markup instead of being escaped, for templates with intentional tags
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub markup: bool,
+}
+
+// module's own appearance, unset fields keep what module shows by default
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ModuleFormat {
+    #[knuffel(child, unwrap(argument))]
+    pub summary_template: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    pub body_template: Option<String>,
+    // ms, overrides module's timeouts except low battery warnings
+    #[knuffel(child, unwrap(argument))]
+    pub timeout: Option<i32>,
+    // low battery warnings stay critical anyway
+    #[knuffel(child, unwrap(argument))]
+    pub urgency: Option<Urgency>,
+}
 
+impl ModuleFormat {
+    pub fn summary(&self, default: &str, values: &[(&str, &str)]) -> String {
+        match self.summary_template {
+            Some(ref template) => render_template(template, values),
+            None => default.into(),
+        }
+    }
+
+    pub fn body(&self, default: &str, values: &[(&str, &str)]) -> String {
+        match self.body_template {
+            Some(ref template) => render_template(template, values),
+            None => default.into(),
+        }
+    }
+
+    pub fn timeout(&self, default: notif::Timeout) -> notif::Timeout {
+        self.timeout.map_or(default, notif::Timeout::from)
+    }
+
+    pub fn urgency(&self) -> notif::Urgency {
+        self.urgency
+            .map_or(notif::Urgency::Normal, notif::Urgency::from)
+    }
+}
+
+// "{name}" is replaced with its value, unknown placeholders are left as is
+pub fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(name, _)| *name == &rest[1..end])
+                .map(|(_, value)| (end, value))
+        });
+
+        match value {
+            Some((end, value)) => {
+                rendered.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+// styling applied to every notification of given urgency, whatever module shows it
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+pub struct UrgencyFormats {
+    #[knuffel(child, default)]
+    pub normal: UrgencyFormat,
+    #[knuffel(child, default)]
+    pub critical: UrgencyFormat,
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct UrgencyFormat {
+    // inserted before icon extension, e.g. "battery-low-alert.svg"
+    #[knuffel(property)]
+    pub icon_suffix: Option<String>,
+    #[knuffel(children(name = "hint"))]
+    pub hints: Vec<ExtraHint>,
+}
+
+// string hints for server specific styling, e.g. dunst's fgcolor/bgcolor/frcolor
+#[derive(knuffel::Decode, Serialize, Clone, Debug)]
+pub struct ExtraHint {
+    #[knuffel(argument)]
+    pub name: String,
+    #[knuffel(argument)]
+    pub value: String,
+}
+
+// warn-at 20 10 urgency="normal" timeout=5000
+#[derive(knuffel::Decode, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Threshold {
+    #[knuffel(arguments)]
+    pub levels: Vec<u8>,
+    #[knuffel(property, default)]
+    pub urgency: Urgency,
+    // never expires by default
+    #[knuffel(property, default = 0)]
+    pub timeout: i32,
+}
+
+fn default_thresholds() -> Vec<Threshold> {
+    vec![Threshold {
+        levels: vec![15],
+        urgency: Urgency::Critical,
+        timeout: 0,
+    }]
+}
+
+// include "common.kdl", see expand_includes
 #[derive(knuffel::Decode, Clone, Debug)]
+pub struct Include {
+    #[knuffel(argument)]
+    pub path: String,
+}
+
+// top-level node of merged config, sections keep their children apart to be merged one by one
+struct MergedNode {
+    name: String,
+    text: String,
+    children: Option<Vec<(String, String)>>,
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
 pub struct Config {
+    // already merged in by the time config is decoded
+    #[knuffel(children(name = "include"))]
+    #[serde(skip)]
+    pub include: Vec<Include>,
+    // how SUN introduces itself to notification server (e.g. for dunst/mako rules) and PulseAudio
+    #[knuffel(child, unwrap(argument), default = notif::APP_NAME.into())]
+    pub app_name: String,
+    #[knuffel(child, unwrap(argument), default)]
+    pub icon_variant: IconVariant,
     #[knuffel(child, unwrap(argument), default = DEFAULT_ERROR_ICON.into())]
     pub error_icon: String,
+    // used when module's icon file and its fallback don't exist, error icon is the last resort
+    #[knuffel(child, unwrap(argument))]
+    pub fallback_icon: Option<String>,
+    // ms, 0 means error notifications never expire
+    #[knuffel(child, unwrap(argument), default = 0)]
+    pub error_timeout: i32,
+    #[knuffel(child, unwrap(argument), default = 0)]
+    pub critical_interval: u64,
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub journal_notifications: bool,
+    // low batteries of all modules are shown in single popup
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub aggregate_low_battery: bool,
+    // latest notification of each module dropped during snooze is shown after it
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub restore_after_snooze: bool,
+    // ms a module may spend handling single event before it's considered stuck, 0 turns it off
+    #[knuffel(child, unwrap(argument), default = 60 * 1000)]
+    pub watchdog_timeout: u64,
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub watchdog_exit: bool,
+    #[knuffel(child, unwrap(argument), default = true)]
+    pub kde_hints: bool,
+    #[knuffel(child, unwrap(argument))]
+    pub kde_origin_name: Option<String>,
+    #[knuffel(child, default)]
+    pub format: Format,
+    // urgency-format { critical icon-suffix="-alert" { hint "fgcolor" "#ff0000"; }; }
+    #[knuffel(child, default)]
+    pub urgency_format: UrgencyFormats,
     #[knuffel(child, default)]
     pub sound: Sound,
     #[knuffel(child, default)]
@@ -44,10 +689,18 @@ pub struct Config {
     pub keyboard: Keyboard,
     #[knuffel(child, default)]
     pub brightness: Brightness,
+    #[knuffel(child, default)]
+    pub thermal: Thermal,
+    #[knuffel(child, default)]
+    pub network: Network,
+    #[knuffel(child, default)]
+    pub lid: Lid,
+    #[knuffel(child, default)]
+    pub lock_keys: LockKeys,
 }
 
 impl Config {
-    pub fn get() -> Self {
+    pub fn get() -> Arc<Self> {
         CONFIG
             .read()
             .unwrap()
@@ -56,27 +709,132 @@ impl Config {
     }
 
     pub fn update() -> Result<Self, KnuffelError> {
-        let config = knuffel::parse::<Config>(
-            CONFIG_FILE,
-            &std::fs::read_to_string(CONFIG_FILE).unwrap_or(include_str!("../config.kdl").into()),
-        )?;
+        let source = Source::get();
+        let (path, mut stack) = match source {
+            Source::File(path) => (
+                Some(path.as_path()),
+                path.canonicalize().into_iter().collect(),
+            ),
+            Source::Stdin(_) => (None, Vec::new()),
+        };
+        let text = expand_includes(&source.to_string(), &source.text(), path, &mut stack)?;
+
+        Self::load(&source.to_string(), &text)
+    }
+
+    pub fn fallback() -> Self {
+        // built-in config is always valid
+        Self::load(CONFIG_FILE, include_str!("../config.kdl")).unwrap()
+    }
+
+    fn load(name: &str, text: &str) -> Result<Self, KnuffelError> {
+        let mut config = knuffel::parse::<Config>(name, text)?;
 
-        *CONFIG.write().unwrap() = Some(config.clone());
+        config.apply_icon_variant();
+
+        *CONFIG.write().unwrap() = Some(Arc::new(config.clone()));
 
         Ok(config)
     }
+
+    // only values left at their defaults are touched, explicit ones always win
+    fn apply_icon_variant(&mut self) {
+        if self.icon_variant == IconVariant::Symbolic {
+            return;
+        }
+
+        let path = |value: &mut String| {
+            if value == DEFAULT_ICON_PATH {
+                *value = DEFAULT_FULLCOLOR_ICON_PATH.into();
+            }
+        };
+        let icon = |value: &mut String, default: &str| {
+            if value == default {
+                *value = default
+                    .replace(DEFAULT_ICON_PATH, DEFAULT_FULLCOLOR_ICON_PATH)
+                    .replace("-symbolic", "");
+            }
+        };
+
+        icon(&mut self.error_icon, DEFAULT_ERROR_ICON);
+
+        path(&mut self.sound.icon_path);
+        icon(&mut self.sound.sink_icon, DEFAULT_SINK_ICON);
+        icon(&mut self.sound.sink_muted_icon, DEFAULT_SINK_MUTED_ICON);
+        icon(
+            &mut self.sound.sink_bluetooth_icon,
+            DEFAULT_SINK_BLUETOOTH_ICON,
+        );
+        icon(&mut self.sound.source_icon, DEFAULT_SOURCE_ICON);
+        icon(&mut self.sound.source_muted_icon, DEFAULT_SOURCE_MUTED_ICON);
+
+        path(&mut self.battery.icon_path);
+        icon(&mut self.battery.full_icon, DEFAULT_BATTERY_FULL_ICON);
+        icon(&mut self.battery.low_icon, DEFAULT_BATTERY_LOW_ICON);
+        icon(&mut self.battery.charger_icon, DEFAULT_BATTERY_CHARGER_ICON);
+        icon(
+            &mut self.battery.charging_icon,
+            DEFAULT_BATTERY_CHARGING_ICON,
+        );
+        icon(
+            &mut self.battery.discharging_icon,
+            DEFAULT_BATTERY_DISCHARGING_ICON,
+        );
+
+        path(&mut self.keyboard.icon_path);
+        icon(&mut self.keyboard.icon, DEFAULT_KEYBOARD_ICON);
+
+        path(&mut self.brightness.icon_path);
+        icon(&mut self.brightness.icon, DEFAULT_BRIGHTNESS_ICON);
+        icon(
+            &mut self.brightness.keyboard_icon,
+            DEFAULT_KEYBOARD_BACKLIGHT_ICON,
+        );
+
+        path(&mut self.thermal.icon_path);
+        icon(&mut self.thermal.icon, DEFAULT_THERMAL_ICON);
+
+        path(&mut self.network.icon_path);
+        icon(
+            &mut self.network.connected_icon,
+            DEFAULT_NETWORK_CONNECTED_ICON,
+        );
+        icon(
+            &mut self.network.disconnected_icon,
+            DEFAULT_NETWORK_DISCONNECTED_ICON,
+        );
+
+        path(&mut self.lid.icon_path);
+        icon(&mut self.lid.open_icon, DEFAULT_LID_ICON);
+        icon(&mut self.lid.closed_icon, DEFAULT_LID_ICON);
+
+        path(&mut self.lock_keys.icon_path);
+        icon(&mut self.lock_keys.caps_lock_icon, DEFAULT_KEYBOARD_ICON);
+        icon(&mut self.lock_keys.num_lock_icon, DEFAULT_KEYBOARD_ICON);
+        icon(&mut self.lock_keys.scroll_lock_icon, DEFAULT_KEYBOARD_ICON);
+    }
 }
 
-#[derive(knuffel::Decode, Clone, Debug, Default)]
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
 pub struct Battery {
     #[knuffel(child)]
     pub off: bool,
-    #[knuffel(child, unwrap(argument), default = DEFAULT_BATTERY_TARGET.into())]
-    pub target: String,
+    #[knuffel(child, unwrap(argument))]
+    pub app_name: Option<String>,
+    // several batteries are reported as one
+    #[knuffel(child, unwrap(arguments), default = vec![DEFAULT_BATTERY_TARGET.into()])]
+    pub target: Vec<String>,
     #[knuffel(child, unwrap(argument), default = 15 * 1000)]
     pub poll_timeout: i32,
-    #[knuffel(child, unwrap(argument), default = 15)]
-    pub warn_at: u8,
+    #[knuffel(children(name = "warn-at"), default = default_thresholds())]
+    pub warn_at: Vec<Threshold>,
+    #[knuffel(child, unwrap(argument), default)]
+    pub warn_action: WarnAction,
+    #[knuffel(child, unwrap(argument))]
+    pub warn_command: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    pub settings_command: Option<String>,
     #[knuffel(child, unwrap(argument), default = DEFAULT_ICON_PATH.into())]
     pub icon_path: String,
     #[knuffel(child, unwrap(argument), default = DEFAULT_BATTERY_FULL_ICON.into())]
@@ -91,12 +849,76 @@ pub struct Battery {
     pub discharging_icon: String,
     #[knuffel(child, unwrap(argument), default = true)]
     pub dynamic_discharging_icon: bool,
+    #[knuffel(child, unwrap(argument), default = 1)]
+    pub min_level: u8,
+    // level-icons { level 70 "bat-70.svg"; level 70 "bat-70-charging.svg" charging=true; }
+    #[knuffel(child, default)]
+    pub level_icons: LevelIcons,
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub show_bar: bool,
+    // {capacity}, {status} and {time} (", ~1h20m" with trend, if known) are replaced
+    #[knuffel(child, unwrap(argument), default = DEFAULT_BATTERY_STATUS_TEMPLATE.into())]
+    pub status_template: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_BATTERY_LOW_TEMPLATE.into())]
+    pub low_template: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_BATTERY_FULL_TEMPLATE.into())]
+    pub full_template: String,
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub notify_on_capacity_change: bool,
+    #[knuffel(child, unwrap(argument), default = true)]
+    pub show_time_estimate: bool,
+    // arrow showing whether power draw is increasing or decreasing
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub show_trend: bool,
+    // separate popup on charger plug/unplug
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub notify_charger: bool,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_BATTERY_CHARGER_ICON.into())]
+    pub charger_icon: String,
+    // used when icon file doesn't exist, relative to icon-path
+    #[knuffel(child, unwrap(argument))]
+    pub fallback_icon: Option<String>,
+    #[knuffel(child, default)]
+    pub format: ModuleFormat,
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+pub struct LevelIcons {
+    #[knuffel(children(name = "level"))]
+    pub icons: Vec<LevelIcon>,
 }
 
-#[derive(knuffel::Decode, Clone, Debug, Default)]
+#[derive(knuffel::Decode, Serialize, Clone, Debug)]
+pub struct LevelIcon {
+    #[knuffel(argument)]
+    pub level: u8,
+    #[knuffel(argument)]
+    pub icon: String,
+    #[knuffel(property, default = false)]
+    pub charging: bool,
+}
+
+impl LevelIcons {
+    // None means {level} template is used for this bucket
+    pub fn get(&self, level: u8, charging: bool) -> Option<&str> {
+        self.icons
+            .iter()
+            .find(|icon| icon.level == level && icon.charging == charging)
+            .map(|icon| icon.icon.as_str())
+    }
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
 pub struct Sound {
     #[knuffel(child)]
     pub off: bool,
+    #[knuffel(child, unwrap(argument))]
+    pub app_name: Option<String>,
+    #[knuffel(child)]
+    pub sink_off: bool,
+    #[knuffel(child)]
+    pub source_off: bool,
     #[knuffel(child, unwrap(argument), default = DEFAULT_ICON_PATH.into())]
     pub icon_path: String,
     #[knuffel(child, unwrap(argument), default = DEFAULT_SINK_ICON.into())]
@@ -113,6 +935,9 @@ pub struct Sound {
     pub sink_bluetooth_low_battery_warn_at: u8,
     #[knuffel(child, unwrap(argument), default = -1)]
     pub sink_bluetooth_low_battery_timeout: i32,
+    // secs, polls don't repeat low battery warning of sink or source during it
+    #[knuffel(child, unwrap(argument), default = 0)]
+    pub bluetooth_low_battery_suppress_window: u64,
     #[knuffel(child, unwrap(argument), default = 2500)]
     pub sink_notification_timeout: i32,
     #[knuffel(child, unwrap(argument), default = DEFAULT_SOURCE_ICON.into())]
@@ -121,56 +946,422 @@ pub struct Sound {
     pub source_muted_icon: String,
     #[knuffel(child, unwrap(argument), default = 2500)]
     pub source_notification_timeout: i32,
+    #[knuffel(child, unwrap(argument), default = 30)]
+    pub source_bluetooth_battery_poll_timeout: u64,
+    #[knuffel(child, unwrap(argument), default = 15)]
+    pub source_bluetooth_low_battery_warn_at: u8,
+    #[knuffel(child, unwrap(argument), default = -1)]
+    pub source_bluetooth_low_battery_timeout: i32,
+    #[knuffel(child, unwrap(argument), default = true)]
+    pub source_show_battery: bool,
+    #[knuffel(child, unwrap(argument), default = true)]
+    pub source_show_low_battery: bool,
+    #[knuffel(child, unwrap(argument), default = 1)]
+    pub volume_display_step: u32,
+    #[knuffel(child, unwrap(argument), default = true)]
+    pub value_hint: bool,
+    // volume notifications aren't kept in history / don't make sound
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub transient: bool,
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub suppress_sound: bool,
+    // volume above 100% is shown in body, value hint is clamped anyway
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub allow_overamplification: bool,
+    // sink changes right after stream is added/removed are considered automatic
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub suppress_auto_changes: bool,
+    // ms, sink changes within it are shown as one notification with the latest state
+    #[knuffel(child, unwrap(argument), default = 0)]
+    pub volume_debounce_ms: u64,
+    #[knuffel(child, unwrap(argument))]
+    pub sink_name: Option<String>,
+    #[knuffel(child, unwrap(argument))]
+    pub source_name: Option<String>,
+    #[knuffel(child, unwrap(argument), default = true)]
+    pub sink_show_description: bool,
+    // "Output switched to ..." body when default sink becomes other device
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub notify_device_change: bool,
+    #[knuffel(child, unwrap(argument), default = true)]
+    pub sink_show_battery: bool,
+    #[knuffel(child, unwrap(argument), default = true)]
+    pub sink_show_low_battery: bool,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_MUTED_SUFFIX.into())]
+    pub muted_suffix: String,
+    // used when icon file doesn't exist, relative to icon-path
+    #[knuffel(child, unwrap(argument))]
+    pub fallback_icon: Option<String>,
+    #[knuffel(child, default)]
+    pub format: ModuleFormat,
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+pub struct LayoutNames {
+    #[knuffel(children)]
+    pub names: Vec<LayoutName>,
 }
 
-#[derive(knuffel::Decode, Clone, Debug, Default)]
+#[derive(knuffel::Decode, Serialize, Clone, Debug)]
+pub struct LayoutName {
+    #[knuffel(node_name)]
+    pub raw: String,
+    #[knuffel(argument)]
+    pub display: String,
+}
+
+impl LayoutNames {
+    // raw name is shown when there is no mapping for it
+    pub fn get<'a>(&'a self, raw: &'a str) -> &'a str {
+        self.names
+            .iter()
+            .find(|name| name.raw == raw)
+            .map_or(raw, |name| &name.display)
+    }
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
 pub struct Keyboard {
     #[knuffel(child)]
     pub off: bool,
+    #[knuffel(child, unwrap(argument))]
+    pub app_name: Option<String>,
     #[knuffel(child, unwrap(argument), default = DEFAULT_ICON_PATH.into())]
     pub icon_path: String,
     #[knuffel(child, unwrap(argument), default = DEFAULT_KEYBOARD_ICON.into())]
     pub icon: String,
+    #[knuffel(child, unwrap(argument), default = true)]
+    pub skip_single_layout: bool,
+    // e.g. "us" instead of "English (US)", only X11 knows them
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub use_short_name: bool,
+    // layout-names { "English (US)" "EN"; "Russian" "RU"; }
+    #[knuffel(child, default)]
+    pub layout_names: LayoutNames,
+    // caps/num lock popups, only X11 reports them
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub lock_notifications: bool,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_KEYBOARD_ICON.into())]
+    pub caps_lock_icon: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_KEYBOARD_ICON.into())]
+    pub num_lock_icon: String,
+    // used when icon file doesn't exist, relative to icon-path
+    #[knuffel(child, unwrap(argument))]
+    pub fallback_icon: Option<String>,
+    #[knuffel(child, default)]
+    pub format: ModuleFormat,
 }
 
-#[derive(knuffel::Decode, Clone, Debug, Default)]
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
 pub struct Brightness {
     #[knuffel(child)]
     pub off: bool,
+    #[knuffel(child, unwrap(argument))]
+    pub app_name: Option<String>,
     #[knuffel(child, unwrap(argument), default = DEFAULT_ICON_PATH.into())]
     pub icon_path: String,
     #[knuffel(child, unwrap(argument), default = DEFAULT_BRIGHTNESS_ICON.into())]
     pub icon: String,
     #[knuffel(child, unwrap(argument))]
     pub target: Option<String>,
+    #[knuffel(child, unwrap(argument), default = 100)]
+    pub cooldown: u64,
+    // ms, backlight is also read periodically, 0 means uevents only
+    #[knuffel(child, unwrap(argument), default = 0)]
+    pub poll_ms: u64,
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub transient: bool,
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub suppress_sound: bool,
+    // external monitor via ddcutil when there is no backlight device, needs i2c access
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub ddc: bool,
+    // ddcutil --display, first monitor when unset
+    #[knuffel(child, unwrap(argument))]
+    pub ddc_display: Option<u32>,
+    // ms, DDC/CI has no change events, used when poll-ms is 0
+    #[knuffel(child, unwrap(argument), default = 2000)]
+    pub ddc_poll_ms: u64,
+    // keyboard backlight (leds/*::kbd_backlight)
+    #[knuffel(child)]
+    pub keyboard_off: bool,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_KEYBOARD_BACKLIGHT_ICON.into())]
+    pub keyboard_icon: String,
+    #[knuffel(child, unwrap(argument))]
+    pub keyboard_target: Option<String>,
+    // used when icon file doesn't exist, relative to icon-path
+    #[knuffel(child, unwrap(argument))]
+    pub fallback_icon: Option<String>,
+    #[knuffel(child, default)]
+    pub format: ModuleFormat,
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Thermal {
+    #[knuffel(child)]
+    pub off: bool,
+    #[knuffel(child, unwrap(argument))]
+    pub app_name: Option<String>,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_ICON_PATH.into())]
+    pub icon_path: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_THERMAL_ICON.into())]
+    pub icon: String,
+    // zone directory names (thermal_zone0) or types (x86_pkg_temp), all zones when empty
+    #[knuffel(child, unwrap(arguments), default)]
+    pub zones: Vec<String>,
+    // °C
+    #[knuffel(child, unwrap(argument), default = 85)]
+    pub warn_at: i32,
+    #[knuffel(child, unwrap(argument), default = 95)]
+    pub critical_at: i32,
+    // ms, zones are also read on thermal uevents (trip points)
+    #[knuffel(child, unwrap(argument), default = 5000)]
+    pub poll_timeout: u64,
+    // used when icon file doesn't exist, relative to icon-path
+    #[knuffel(child, unwrap(argument))]
+    pub fallback_icon: Option<String>,
+    #[knuffel(child, default)]
+    pub format: ModuleFormat,
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Network {
+    #[knuffel(child)]
+    pub off: bool,
+    #[knuffel(child, unwrap(argument))]
+    pub app_name: Option<String>,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_ICON_PATH.into())]
+    pub icon_path: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_NETWORK_CONNECTED_ICON.into())]
+    pub connected_icon: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_NETWORK_DISCONNECTED_ICON.into())]
+    pub disconnected_icon: String,
+    // interface-icons { "wlan0" "wireless.svg" "wireless-offline.svg"; }
+    #[knuffel(child, default)]
+    pub interface_icons: InterfaceIcons,
+    // only these interfaces are watched when set, "veth*" matches by prefix
+    #[knuffel(child, unwrap(arguments), default)]
+    pub interfaces: Vec<String>,
+    #[knuffel(child, unwrap(arguments), default)]
+    pub ignore: Vec<String>,
+    // bridges, veths, tunnels etc. have no device behind them
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub include_virtual: bool,
+    // used when icon file doesn't exist, relative to icon-path
+    #[knuffel(child, unwrap(argument))]
+    pub fallback_icon: Option<String>,
+    #[knuffel(child, default)]
+    pub format: ModuleFormat,
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+pub struct InterfaceIcons {
+    #[knuffel(children)]
+    pub icons: Vec<InterfaceIcon>,
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug)]
+pub struct InterfaceIcon {
+    #[knuffel(node_name)]
+    pub interface: String,
+    #[knuffel(argument)]
+    pub connected: String,
+    #[knuffel(argument)]
+    pub disconnected: String,
+}
+
+impl Network {
+    pub fn icon(&self, interface: &str, connected: bool) -> &str {
+        let icon = self
+            .interface_icons
+            .icons
+            .iter()
+            .find(|icon| icon.interface == interface);
+
+        match (icon, connected) {
+            (Some(icon), true) => &icon.connected,
+            (Some(icon), false) => &icon.disconnected,
+            (None, true) => &self.connected_icon,
+            (None, false) => &self.disconnected_icon,
+        }
+    }
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Lid {
+    #[knuffel(child)]
+    pub off: bool,
+    #[knuffel(child, unwrap(argument))]
+    pub app_name: Option<String>,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_ICON_PATH.into())]
+    pub icon_path: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_LID_ICON.into())]
+    pub open_icon: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_LID_ICON.into())]
+    pub closed_icon: String,
+    #[knuffel(child, unwrap(argument), default = "Lid opened".into())]
+    pub open_message: String,
+    #[knuffel(child, unwrap(argument), default = "Lid closed".into())]
+    pub closed_message: String,
+    // ms, only when lid switch can't be read via evdev and /proc/acpi is polled instead
+    #[knuffel(child, unwrap(argument), default = 1000)]
+    pub poll_timeout: u64,
+    // used when icon file doesn't exist, relative to icon-path
+    #[knuffel(child, unwrap(argument))]
+    pub fallback_icon: Option<String>,
+    #[knuffel(child, default)]
+    pub format: ModuleFormat,
+}
+
+#[derive(knuffel::Decode, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct LockKeys {
+    #[knuffel(child)]
+    pub off: bool,
+    #[knuffel(child, unwrap(argument))]
+    pub app_name: Option<String>,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_ICON_PATH.into())]
+    pub icon_path: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_KEYBOARD_ICON.into())]
+    pub caps_lock_icon: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_KEYBOARD_ICON.into())]
+    pub num_lock_icon: String,
+    #[knuffel(child, unwrap(argument), default = DEFAULT_KEYBOARD_ICON.into())]
+    pub scroll_lock_icon: String,
+    // "capslock", "numlock", "scrolllock" (led names), all of them when empty
+    #[knuffel(child, unwrap(arguments), default)]
+    pub keys: Vec<String>,
+    // popup stays while lock is on and is closed once it's off
+    #[knuffel(child, unwrap(argument), default = false)]
+    pub persistent: bool,
+    // ms, led brightness changes don't emit uevents, only keyboards (un)plugging does
+    #[knuffel(child, unwrap(argument), default = 500)]
+    pub poll_timeout: u64,
+    // used when icon file doesn't exist, relative to icon-path
+    #[knuffel(child, unwrap(argument))]
+    pub fallback_icon: Option<String>,
+    #[knuffel(child, default)]
+    pub format: ModuleFormat,
 }
 
 pub fn routine(sender: Sender<Message>) -> impl crate::Routine {
     move || {
-        let mut inotify = Inotify::init().unwrap();
-        let mut buf =
-            vec![0; inotify::get_buffer_size(&std::path::Path::new(CONFIG_FILE)).unwrap()];
-
-        inotify
-            .watches()
-            .add(CONFIG_FILE, WatchMask::MODIFY)
-            .unwrap();
+        // stdin is read only once, nothing to watch
+        let Source::File(path) = Source::get() else {
+            return;
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let Some(mut inotify) = watch(dir, path) else {
+            return;
+        };
+        let mut buf = vec![0; 4096];
 
         loop {
-            for ev in inotify.read_events_blocking(&mut buf).unwrap() {
+            let events = inotify.read_events_blocking(&mut buf).unwrap();
+
+            // both watches report the same write
+            if needs_reload(&mut inotify, path, events) {
                 match Config::update() {
                     Ok(config) => sender.send(Message::ConfigReload(config)).unwrap(),
                     Err(err) => sender.send(Message::ConfigReloadError(err)).unwrap(),
                 }
+            }
+        }
+    }
+}
 
-                if ev.mask & EventMask::IGNORED == EventMask::IGNORED {
-                    match inotify.watches().add(CONFIG_FILE, WatchMask::MODIFY) {
-                        Err(err) if matches!(err.kind(), ErrorKind::NotFound) => (),
-                        Err(err) => panic!("inotify add watch error:\n{err:#?}"),
-                        _ => (),
-                    }
-                }
+// None when config directory doesn't exist
+fn watch(dir: &Path, path: &Path) -> Option<Inotify> {
+    let mut inotify = Inotify::init().unwrap();
+
+    // editors often save by renaming temp file over config, which drops the file watch,
+    // so directory is watched for config to reappear (or to be created later)
+    match inotify
+        .watches()
+        .add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+    {
+        Err(err) if matches!(err.kind(), ErrorKind::NotFound) => {
+            info!("{} not found, using defaults", dir.display());
+            return None;
+        }
+        Err(err) => panic!("inotify add watch error:\n{err:#?}"),
+        _ => (),
+    }
+
+    // file itself is watched too, symlinked config is changed where it points to
+    watch_file(&mut inotify, path);
+
+    Some(inotify)
+}
+
+fn needs_reload(inotify: &mut Inotify, path: &Path, events: Events<'_>) -> bool {
+    let mut reload = false;
+
+    for ev in events {
+        if ev.mask.contains(EventMask::IGNORED) {
+            continue;
+        }
+
+        match ev.name {
+            // event of the file watch
+            None => reload = true,
+            Some(name) if Some(name) == path.file_name() => {
+                reload = true;
+                watch_file(inotify, path);
             }
+            Some(_) => (),
         }
     }
+
+    reload
+}
+
+// config may be missing or already replaced again, directory watch brings it back then
+fn watch_file(inotify: &mut Inotify, path: &Path) {
+    match inotify.watches().add(path, WatchMask::CLOSE_WRITE) {
+        Err(err) if matches!(err.kind(), ErrorKind::NotFound) => (),
+        Err(err) => panic!("inotify add watch error:\n{err:#?}"),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_on_config_renamed_over() {
+        let dir = std::env::temp_dir().join(format!("sun-config-test-{}", std::process::id()));
+        let path = dir.join(CONFIG_FILE);
+        let tmp = dir.join("config.kdl.tmp");
+        let mut buf = vec![0; 4096];
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, "").unwrap();
+
+        let mut inotify = watch(&dir, &path).unwrap();
+
+        // the way editors save, kernel queues the events before rename returns
+        std::fs::write(&tmp, "app-name \"test\"").unwrap();
+        std::fs::rename(&tmp, &path).unwrap();
+
+        let events = inotify.read_events(&mut buf).unwrap();
+        assert!(needs_reload(&mut inotify, &path, events));
+
+        // and so does a plain write after it
+        std::fs::write(&path, "").unwrap();
+
+        let events = inotify.read_events(&mut buf).unwrap();
+        assert!(needs_reload(&mut inotify, &path, events));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
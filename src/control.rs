@@ -0,0 +1,73 @@
+use crate::config::{self, Config};
+use crate::notif;
+use crate::{Message, Module};
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use zbus::blocking::connection;
+
+const BUS_NAME: &'static str = "io.github.tyda_syda.Sun";
+const OBJ_PATH: &'static str = "/io/github/tyda_syda/Sun";
+
+struct Control {
+    sender: Sender<Message>,
+}
+
+#[zbus::interface(name = "io.github.tyda_syda.Sun")]
+impl Control {
+    /// Makes every running module show its current state
+    fn show_all(&self) {
+        self.sender.send(Message::ShowAll).unwrap();
+    }
+
+    /// Drops all non-critical notifications for given amount of seconds, 0 lifts snooze
+    fn snooze(&self, seconds: u64) -> zbus::fdo::Result<()> {
+        notif::snooze(Duration::from_secs(seconds)).map_err(zbus::fdo::Error::InvalidArgs)
+    }
+
+    /// Shows test notification of given module, works even if module is off
+    fn test_notify(&self, module: &str) -> zbus::fdo::Result<()> {
+        let module = Module::from_str(module).map_err(zbus::fdo::Error::InvalidArgs)?;
+
+        // notification may wait for critical interval, don't block the bus
+        std::thread::spawn(move || crate::test_notification(module).show());
+        Ok(())
+    }
+
+    /// Rereads config, same as if config file was changed
+    fn reload(&self) -> zbus::fdo::Result<()> {
+        let config = Config::update().map_err(|err| {
+            error!("config parse error:\n{}", config::describe_error(&err));
+            zbus::fdo::Error::Failed("config parse error, check logs for details".into())
+        })?;
+
+        self.sender.send(Message::ConfigReload(config)).unwrap();
+        Ok(())
+    }
+
+    /// Returns effective config (with all defaults applied) as JSON
+    fn get_config(&self) -> String {
+        serde_json::to_string_pretty(&*Config::get()).unwrap()
+    }
+}
+
+pub fn routine(sender: Sender<Message>) -> impl crate::Routine {
+    move || {
+        // connection serves requests on its own executor thread, just keep it alive
+        let _conn = match connection::Builder::session()
+            .and_then(|builder| builder.name(BUS_NAME))
+            .and_then(|builder| builder.serve_at(OBJ_PATH, Control { sender }))
+            .and_then(|builder| builder.build())
+        {
+            Ok(conn) => conn,
+            Err(err) => {
+                error!("control interface is unavailable: {err}");
+                return;
+            }
+        };
+
+        loop {
+            std::thread::park();
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use std::io::Result;
+use std::os::unix::net::UnixDatagram;
+
+const JOURNAL_SOCKET: &'static str = "/run/systemd/journal/socket";
+
+pub const PRIORITY_CRIT: u8 = 2;
+pub const PRIORITY_ERR: u8 = 3;
+pub const PRIORITY_WARNING: u8 = 4;
+pub const PRIORITY_INFO: u8 = 6;
+pub const PRIORITY_DEBUG: u8 = 7;
+
+// native journal protocol, see systemd.journal-fields(7) and
+// https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+pub fn send(fields: &[(&str, &str)]) -> Result<()> {
+    let mut msg = Vec::new();
+
+    for (name, val) in fields {
+        msg.extend_from_slice(name.as_bytes());
+
+        // multiline values have to be length prefixed
+        if val.contains('\n') {
+            msg.push(b'\n');
+            msg.extend_from_slice(&(val.len() as u64).to_le_bytes());
+        } else {
+            msg.push(b'=');
+        }
+
+        msg.extend_from_slice(val.as_bytes());
+        msg.push(b'\n');
+    }
+
+    UnixDatagram::unbound()?.send_to(&msg, JOURNAL_SOCKET)?;
+
+    Ok(())
+}
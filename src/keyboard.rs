@@ -1,12 +1,41 @@
-use crate::config::Config;
-use crate::notif::{Notification, Timeout};
+use crate::config::{Config, DEFAULT_KEYBOARD_ICON};
+use crate::notif::{self, Notification, Timeout};
+use crate::Module;
 use serde_json;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
 use std::net::Shutdown;
 use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(feature = "x11")]
 use xcb::xkb;
 
-type LayoutFunc = Box<dyn FnMut() -> Result<String, Error>>;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const SWAY_IPC_MAGIC: &[u8] = b"i3-ipc";
+const SWAY_IPC_HEADER_LEN: usize = SWAY_IPC_MAGIC.len() + 8; // + payload length and type
+const SWAY_IPC_SUBSCRIBE: u32 = 2;
+const SWAY_IPC_EVENT_INPUT: u32 = 0x80000015;
+
+type EventFunc = Box<dyn FnMut() -> Result<Event, Error>>;
+
+#[cfg_attr(not(feature = "x11"), allow(dead_code))]
+enum Event {
+    Layout(Layout),
+    Lock(LockKey, bool), // on/off, only X11 reports it
+}
+
+#[cfg_attr(not(feature = "x11"), allow(dead_code))]
+#[derive(Clone, Copy, Debug)]
+enum LockKey {
+    Caps,
+    Num,
+}
+
+struct Layout {
+    name: String,
+    short_name: Option<String>, // e.g. "us", if provider knows it
+    count: usize,
+}
 
 mod niri {
     use serde::{Deserialize, Serialize};
@@ -34,6 +63,7 @@ mod niri {
     }
 }
 
+#[cfg(feature = "x11")]
 fn map_xcb_err(err: xcb::Error) -> Error {
     match err {
         xcb::Error::Connection(xcb::ConnError::Connection) => Error::last_os_error().into(),
@@ -46,7 +76,53 @@ fn map_xcb_err(err: xcb::Error) -> Error {
     }
 }
 
-fn x11() -> Option<LayoutFunc> {
+// "pc+us+ru:2+inet(evdev)+group(alt_shift_toggle)" -> [Some("us"), Some("ru")],
+// index is a group, which is taken from ":N" suffix (first one has none)
+#[cfg(feature = "x11")]
+fn parse_xkb_symbols(symbols: &str) -> Vec<Option<String>> {
+    const NON_LAYOUTS: [&str; 16] = [
+        "pc",
+        "inet",
+        "group",
+        "compose",
+        "ctrl",
+        "caps",
+        "capslock",
+        "level3",
+        "level5",
+        "altwin",
+        "terminate",
+        "keypad",
+        "kpdl",
+        "shift",
+        "eurosign",
+        "srvr_ctrl",
+    ];
+    let mut layouts = Vec::new();
+
+    for part in symbols.split('+') {
+        let (part, group) = match part.split_once(':') {
+            Some((part, group)) => (part, group.parse::<usize>().unwrap_or(1)),
+            None => (part, 1),
+        };
+        let code = part.split('(').next().unwrap_or(part);
+
+        if code.is_empty() || NON_LAYOUTS.contains(&code) || group == 0 {
+            continue;
+        }
+
+        if layouts.len() < group {
+            layouts.resize(group, None);
+        }
+
+        layouts[group - 1].get_or_insert(code.to_owned());
+    }
+
+    layouts
+}
+
+#[cfg(feature = "x11")]
+fn x11() -> Option<EventFunc> {
     let conn = xcb::Connection::connect_with_extensions(None, &[xcb::Extension::Xkb], &[])
         .ok()?
         .0;
@@ -64,11 +140,13 @@ fn x11() -> Option<LayoutFunc> {
 
     let core_kbd = xkb::Id::UseCoreKbd as u16;
 
+    let events = xkb::EventType::STATE_NOTIFY | xkb::EventType::INDICATOR_STATE_NOTIFY;
+
     conn.check_request(conn.send_request_checked(&xkb::SelectEvents {
         device_spec: core_kbd,
-        affect_which: xkb::EventType::STATE_NOTIFY,
+        affect_which: events,
         clear: xkb::EventType::empty(),
-        select_all: xkb::EventType::STATE_NOTIFY,
+        select_all: events,
         affect_map: xkb::MapPart::empty(),
         map: xkb::MapPart::empty(),
         details: &[],
@@ -82,8 +160,46 @@ fn x11() -> Option<LayoutFunc> {
         .ok()?
         .group();
 
+    // indicator bits differ between setups, so they are looked up by name
+    let indicators = [(LockKey::Caps, "Caps Lock"), (LockKey::Num, "Num Lock")]
+        .into_iter()
+        .filter_map(|(key, name)| {
+            let atom = conn
+                .wait_for_reply(conn.send_request(&xcb::x::InternAtom {
+                    only_if_exists: true,
+                    name: name.as_bytes(),
+                }))
+                .ok()?
+                .atom();
+            let reply = conn
+                .wait_for_reply(conn.send_request(&xkb::GetNamedIndicator {
+                    device_spec: core_kbd,
+                    led_class: xkb::LedClass::DfltXiClass,
+                    led_id: xkb::Id::DfltXiId as u16,
+                    indicator: atom,
+                }))
+                .ok()?;
+
+            reply.found().then_some((key, 1u32 << reply.ndx()))
+        })
+        .collect::<Vec<_>>();
+    let mut pending = Vec::new(); // single event may change several indicators
+
     let func = move || loop {
+        if let Some(ev) = pending.pop() {
+            break Ok(ev);
+        }
+
         break match conn.wait_for_event() {
+            Ok(xcb::Event::Xkb(xkb::Event::IndicatorStateNotify(ev))) => {
+                for (key, bit) in indicators.iter() {
+                    if ev.state_changed() & bit != 0 {
+                        pending.push(Event::Lock(*key, ev.state() & bit != 0));
+                    }
+                }
+
+                continue;
+            }
             Ok(xcb::Event::Xkb(xkb::Event::StateNotify(state))) => {
                 if state.group() == current_group {
                     continue;
@@ -91,28 +207,38 @@ fn x11() -> Option<LayoutFunc> {
 
                 current_group = state.group();
 
-                conn.wait_for_reply(conn.send_request(&xkb::GetNames {
-                    device_spec: core_kbd,
-                    which: xkb::NameDetail::GROUP_NAMES,
+                let atom_name = |atom: xcb::x::Atom| {
+                    conn.wait_for_reply(conn.send_request(&xcb::x::GetAtomName { atom }))
+                        .map(|reply| reply.name().as_ascii().to_owned())
+                };
+                let reply = conn
+                    .wait_for_reply(conn.send_request(&xkb::GetNames {
+                        device_spec: core_kbd,
+                        which: xkb::NameDetail::GROUP_NAMES | xkb::NameDetail::SYMBOLS,
+                    }))
+                    .map_err(map_xcb_err)?;
+                let mut names = Vec::new();
+                let mut symbols = Vec::new();
+
+                for val in reply.value_list() {
+                    match val {
+                        xkb::GetNamesReplyValueList::GroupNames(atoms) => {
+                            for atom in atoms {
+                                names.push(atom_name(atom).map_err(map_xcb_err)?);
+                            }
+                        }
+                        xkb::GetNamesReplyValueList::Symbols(atom) => {
+                            symbols = parse_xkb_symbols(&atom_name(atom).map_err(map_xcb_err)?);
+                        }
+                        _ => (),
+                    }
+                }
+
+                Ok(Event::Layout(Layout {
+                    name: names[current_group as usize].clone(),
+                    short_name: symbols.get(current_group as usize).cloned().flatten(),
+                    count: names.len(),
                 }))
-                .map_err(map_xcb_err)?
-                .value_list()
-                .iter()
-                .filter_map(|val| match val {
-                    xkb::GetNamesReplyValueList::GroupNames(atoms) => Some(atoms),
-                    _ => None,
-                })
-                .flat_map(|atoms| atoms)
-                .map(|atom| {
-                    conn.wait_for_reply(conn.send_request(&xcb::x::GetAtomName { atom: *atom }))
-                        .unwrap()
-                        .name()
-                        .as_ascii()
-                        .to_owned()
-                })
-                .nth(current_group as usize)
-                .map(|layout| Ok(layout))
-                .unwrap()
             }
             Ok(_) => {
                 continue;
@@ -124,29 +250,46 @@ fn x11() -> Option<LayoutFunc> {
     Some(Box::new(func))
 }
 
-fn niri() -> Option<LayoutFunc> {
+// do not use BufReader::read_line() here
+// it ignores EINTR inside of BufReader::read_until()
+fn read_line<R: Read>(buf_reader: &mut BufReader<R>, line: &mut Vec<u8>) -> Result<String, Error> {
+    loop {
+        let buf = buf_reader.fill_buf()?;
+
+        // socket is closed (e.g. compositor exited), otherwise it'd be spinning here forever
+        if buf.is_empty() {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        // message may span several fills, so keep everything seen so far in `line`
+        match buf.iter().position(|b| *b == b'\n') {
+            Some(idx) => {
+                line.extend_from_slice(&buf[..idx]);
+                buf_reader.consume(idx + 1);
+                return Ok(String::from_utf8_lossy(&std::mem::take(line)).into_owned());
+            }
+            None => {
+                let num = buf.len();
+
+                line.extend_from_slice(buf);
+                buf_reader.consume(num);
+            }
+        }
+    }
+}
+
+fn niri() -> Option<EventFunc> {
     let mut sock = UnixStream::connect(std::env::var("NIRI_SOCKET").ok()?).ok()?;
     let mut buf_reader = BufReader::new(sock.try_clone().unwrap());
     let mut layouts = Vec::new();
+    let mut line = Vec::new();
 
     sock.write_all(b"\"EventStream\"\n").unwrap();
     sock.shutdown(Shutdown::Write).unwrap();
     buf_reader.read_line(&mut String::new()).unwrap(); // discard OK reponse
 
     let func = move || loop {
-        // do not use BufReader::read_line() here
-        // it ignores EINTR inside of BufReader::read_until()
-        let (msg, num) = 'outer: loop {
-            let msg = buf_reader.fill_buf().map_err(|e| e.kind())?;
-
-            for idx in 0..msg.len() {
-                if msg[idx] == b'\n' {
-                    break 'outer (String::from_utf8(Vec::from(&msg[..idx])).unwrap(), idx + 1);
-                }
-            }
-        };
-
-        buf_reader.consume(num);
+        let msg = read_line(&mut buf_reader, &mut line)?;
 
         break match serde_json::from_str::<niri::Response>(&msg) {
             Ok(niri::Response::KeyboardLayoutsChanged(niri::KeyboardLayoutsChanged {
@@ -158,7 +301,11 @@ fn niri() -> Option<LayoutFunc> {
                 continue;
             }
             Ok(niri::Response::KeyboardLayoutSwitched(niri::KeyboardLayoutSwitched { idx })) => {
-                Ok(layouts[idx as usize].clone())
+                Ok(Event::Layout(Layout {
+                    name: layouts[idx as usize].clone(),
+                    short_name: None, // niri reports only full names
+                    count: layouts.len(),
+                }))
             }
             Err(_) => continue, // ignore non keyboard related events
         };
@@ -167,45 +314,401 @@ fn niri() -> Option<LayoutFunc> {
     Some(Box::new(func))
 }
 
-fn layout_provider() -> LayoutFunc {
+mod hyprland {
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug)]
+    pub(super) struct Devices {
+        pub keyboards: Vec<Keyboard>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub(super) struct Keyboard {
+        pub layout: String,
+        pub main: bool,
+    }
+}
+
+mod sway {
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug)]
+    pub(super) struct InputEvent {
+        pub change: String,
+        pub input: Input,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub(super) struct Input {
+        #[serde(rename = "type")]
+        pub kind: String,
+        #[serde(default)]
+        pub xkb_layout_names: Vec<String>,
+        pub xkb_active_layout_name: Option<String>,
+    }
+}
+
+fn sway_ipc_message(kind: u32, payload: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(SWAY_IPC_HEADER_LEN + payload.len());
+
+    msg.extend_from_slice(SWAY_IPC_MAGIC);
+    msg.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    msg.extend_from_slice(&kind.to_ne_bytes());
+    msg.extend_from_slice(payload);
+    msg
+}
+
+// takes (type, payload) of first complete message out of buf, if there is one
+fn take_sway_ipc_message(buf: &mut Vec<u8>) -> Result<Option<(u32, Vec<u8>)>, Error> {
+    if buf.len() < SWAY_IPC_HEADER_LEN {
+        return Ok(None);
+    }
+
+    if !buf.starts_with(SWAY_IPC_MAGIC) {
+        return Err(Error::new(ErrorKind::InvalidData, "invalid sway ipc magic"));
+    }
+
+    let field = |idx: usize| {
+        let start = SWAY_IPC_MAGIC.len() + idx * 4;
+        u32::from_ne_bytes(buf[start..start + 4].try_into().unwrap())
+    };
+    let (len, kind) = (field(0) as usize, field(1));
+
+    if buf.len() < SWAY_IPC_HEADER_LEN + len {
+        return Ok(None);
+    }
+
+    let payload = buf[SWAY_IPC_HEADER_LEN..SWAY_IPC_HEADER_LEN + len].to_vec();
+
+    buf.drain(..SWAY_IPC_HEADER_LEN + len);
+    Ok(Some((kind, payload)))
+}
+
+fn sway() -> Option<EventFunc> {
+    let mut sock = UnixStream::connect(std::env::var("SWAYSOCK").ok()?).ok()?;
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    sock.write_all(&sway_ipc_message(SWAY_IPC_SUBSCRIBE, br#"["input"]"#))
+        .ok()?;
+
+    // do not use read_exact() here, it ignores EINTR,
+    // partially read messages are kept in `buf` between calls instead
+    let func = move || loop {
+        let (kind, payload) = match take_sway_ipc_message(&mut buf)? {
+            Some(msg) => msg,
+            None => match sock.read(&mut chunk)? {
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                num => {
+                    buf.extend_from_slice(&chunk[..num]);
+                    continue;
+                }
+            },
+        };
+
+        // subscribe reply and other events
+        if kind != SWAY_IPC_EVENT_INPUT {
+            continue;
+        }
+
+        match serde_json::from_slice::<sway::InputEvent>(&payload) {
+            Ok(ev) if ev.change == "xkb_layout" && ev.input.kind == "keyboard" => {
+                if let Some(name) = ev.input.xkb_active_layout_name {
+                    break Ok(Event::Layout(Layout {
+                        name,
+                        short_name: None, // sway reports only full names
+                        count: ev.input.xkb_layout_names.len(),
+                    }));
+                }
+            }
+            _ => continue, // ignore non keyboard inputs
+        }
+    };
+
+    Some(Box::new(func))
+}
+
+fn hyprland_socket(name: &str) -> Option<PathBuf> {
+    let runtime_dir = std::env::var("XDG_RUNTIME_DIR").ok()?;
+    let signature = std::env::var("HYPRLAND_INSTANCE_SIGNATURE").ok()?;
+
+    Some(PathBuf::from(format!(
+        "{runtime_dir}/hypr/{signature}/{name}"
+    )))
+}
+
+// events don't carry layout count, so it's asked from main keyboard separately
+fn hyprland_layout_count() -> Option<usize> {
+    let mut sock = UnixStream::connect(hyprland_socket(".socket.sock")?).ok()?;
+    let mut reply = String::new();
+
+    sock.write_all(b"j/devices").ok()?;
+    sock.read_to_string(&mut reply).ok()?;
+
+    let devices = serde_json::from_str::<hyprland::Devices>(&reply).ok()?;
+    let keyboard = devices.keyboards.iter().find(|keyboard| keyboard.main)?;
+
+    Some(keyboard.layout.split(',').count())
+}
+
+fn hyprland() -> Option<EventFunc> {
+    let sock = UnixStream::connect(hyprland_socket(".socket2.sock")?).ok()?;
+    let mut buf_reader = BufReader::new(sock);
+    let mut line = Vec::new();
+    let mut last_layout = None;
+
+    let func = move || loop {
+        let msg = read_line(&mut buf_reader, &mut line)?;
+
+        // activelayout>>KEYBOARDNAME,LAYOUTNAME
+        let Some((_, layout)) = msg
+            .strip_prefix("activelayout>>")
+            .and_then(|data| data.rsplit_once(','))
+        else {
+            continue;
+        };
+
+        // event is sent for every keyboard device on a single switch
+        if last_layout.as_deref() == Some(layout) {
+            continue;
+        }
+
+        last_layout = Some(layout.to_owned());
+
+        break Ok(Event::Layout(Layout {
+            name: layout.to_owned(),
+            short_name: None,
+            count: hyprland_layout_count().unwrap_or(2), // don't skip when unknown
+        }));
+    };
+
+    Some(Box::new(func))
+}
+
+fn event_provider() -> EventFunc {
     if let Some(niri_layout) = niri() {
         return niri_layout;
     };
 
+    if let Some(sway_layout) = sway() {
+        return sway_layout;
+    };
+
+    if let Some(hyprland_layout) = hyprland() {
+        return hyprland_layout;
+    };
+
+    #[cfg(feature = "x11")]
     if let Some(x11_layout) = x11() {
         return x11_layout;
     };
 
-    panic!("neither niri nor X11 with KBD found");
+    panic!("neither niri, sway, hyprland nor X11 with KBD found");
+}
+
+pub fn test_notification() -> Notification {
+    let config = Config::get();
+    let keyboard_config = &config.keyboard;
+    let mut notif = Notification::new();
+
+    notif
+        .module(Module::Keyboard)
+        .app_name(&notif::app_name(&keyboard_config.app_name))
+        .urgency(keyboard_config.format.urgency())
+        .timeout(keyboard_config.format.timeout(Timeout::Millis(2500)))
+        .summary("Layout")
+        .body("Test")
+        .icon_in(
+            &keyboard_config.icon_path,
+            // there is no real layout to substitute
+            if keyboard_config.icon.contains("{layout}") {
+                DEFAULT_KEYBOARD_ICON
+            } else {
+                &keyboard_config.icon
+            },
+        );
+
+    notif
+}
+
+// {layout} can only be expanded for layouts listed in layout-names
+pub fn icons() -> Vec<String> {
+    let config = Config::get();
+    let keyboard_config = &config.keyboard;
+    let mut icons = vec![
+        keyboard_config.caps_lock_icon.clone(),
+        keyboard_config.num_lock_icon.clone(),
+    ];
+
+    if keyboard_config.icon.contains("{layout}") {
+        icons.push(DEFAULT_KEYBOARD_ICON.into());
+        icons.extend(
+            keyboard_config
+                .layout_names
+                .names
+                .iter()
+                .map(|name| keyboard_config.icon.replace("{layout}", &name.raw)),
+        );
+    } else {
+        icons.push(keyboard_config.icon.clone());
+    }
+
+    icons
+        .iter()
+        .map(|icon| notif::join_icon(&keyboard_config.icon_path, icon))
+        .collect()
+}
+
+fn show_lock_notification(notif: &mut Notification, key: LockKey, on: bool) {
+    let config = Config::get();
+    let keyboard_config = &config.keyboard;
+    let (name, icon) = match key {
+        LockKey::Caps => ("Caps Lock", &keyboard_config.caps_lock_icon),
+        LockKey::Num => ("Num Lock", &keyboard_config.num_lock_icon),
+    };
+
+    let status = if on { "on" } else { "off" };
+    let values = [("name", name), ("status", status)];
+
+    notif
+        .app_name(&notif::app_name(&keyboard_config.app_name))
+        .summary(
+            &keyboard_config
+                .format
+                .summary(&format!("{name} {status}"), &values),
+        )
+        .body(&keyboard_config.format.body("", &values))
+        .urgency(keyboard_config.format.urgency())
+        .timeout(keyboard_config.format.timeout(Timeout::Millis(2500)))
+        .icon_in(&keyboard_config.icon_path, icon)
+        .show();
 }
 
 pub fn routine() -> impl crate::Routine {
     || {
         let mut notif = Notification::new();
-        let mut get_layout = layout_provider();
+        let mut lock_notif = Notification::new();
+        let mut get_event = event_provider();
 
-        notif.timeout(Timeout::Millis(2500)).summary("Layout");
+        notif
+            .module(Module::Keyboard)
+            .replace_tag("sun-keyboard-layout");
+        lock_notif
+            .module(Module::Keyboard)
+            .replace_tag("sun-keyboard-lock");
 
         loop {
-            let keyboard_config = Config::get().keyboard;
+            let config = Config::get();
+            let keyboard_config = &config.keyboard;
 
-            if keyboard_config.off {
+            if keyboard_config.off || crate::shutting_down() {
                 break;
             }
 
-            let layout = match get_layout() {
-                Ok(layout) => layout,
+            // providers report only switches, so there is nothing to show before the first one
+            if crate::take_refresh(Module::Keyboard) && !notif.body.is_empty() {
+                notif.show();
+            }
+
+            crate::set_busy(Module::Keyboard, false);
+            let event = get_event();
+            crate::set_busy(Module::Keyboard, true);
+
+            let layout = match event {
+                Ok(Event::Lock(key, on)) => {
+                    if keyboard_config.lock_notifications {
+                        show_lock_notification(&mut lock_notif, key, on);
+                    }
+
+                    continue;
+                }
+                Ok(Event::Layout(layout))
+                    if layout.count < 2 && keyboard_config.skip_single_layout =>
+                {
+                    continue
+                }
+                Ok(Event::Layout(layout)) if keyboard_config.use_short_name => {
+                    layout.short_name.unwrap_or(layout.name)
+                }
+                Ok(Event::Layout(layout)) => layout.name,
                 Err(err) if matches!(err.kind(), ErrorKind::Interrupted) => continue,
+                Err(err) if matches!(err.kind(), ErrorKind::UnexpectedEof) => {
+                    warn!("layout provider connection closed, reconnecting");
+                    std::thread::sleep(RECONNECT_DELAY);
+                    get_event = event_provider();
+                    continue;
+                }
                 Err(err) => panic!("{err:#?}"),
             };
 
+            let mut icon = notif::join_icon(
+                &keyboard_config.icon_path,
+                &keyboard_config.icon.replace("{layout}", &layout),
+            );
+
+            // not every layout has its own icon in a set, icon names are up to server though
+            if keyboard_config.icon.contains("{layout}")
+                && icon.starts_with('/')
+                && !std::path::Path::new(&icon).exists()
+            {
+                icon = notif::join_icon(&keyboard_config.icon_path, DEFAULT_KEYBOARD_ICON);
+            }
+
+            let display = keyboard_config.layout_names.get(&layout);
+            let values = [("name", "Layout"), ("layout", display)];
+
             notif
-                .body(&layout)
-                .icon(&format!(
-                    "{}{}",
-                    keyboard_config.icon_path, keyboard_config.icon
-                ))
+                .app_name(&notif::app_name(&keyboard_config.app_name))
+                .summary(&keyboard_config.format.summary("Layout", &values))
+                .body(&keyboard_config.format.body(display, &values))
+                .urgency(keyboard_config.format.urgency())
+                .timeout(keyboard_config.format.timeout(Timeout::Millis(2500)))
+                .icon(&icon)
                 .show();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // hands out one chunk per read, like a socket with data arriving in pieces
+    struct Chunks(VecDeque<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let Some(chunk) = self.0.pop_front() else {
+                return Ok(0);
+            };
+
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn read_line_joins_line_split_across_fills() {
+        let chunks = Chunks(VecDeque::from([
+            &b"{\"KeyboardLayoutSwitched\":"[..],
+            &b"{\"idx\":1}}\n{\"Ok\""[..],
+            &b":null}\n"[..],
+        ]));
+        let mut buf_reader = BufReader::new(chunks);
+        let mut line = Vec::new();
+
+        assert_eq!(
+            read_line(&mut buf_reader, &mut line).unwrap(),
+            "{\"KeyboardLayoutSwitched\":{\"idx\":1}}"
+        );
+        assert_eq!(
+            read_line(&mut buf_reader, &mut line).unwrap(),
+            "{\"Ok\":null}"
+        );
+        assert_eq!(
+            read_line(&mut buf_reader, &mut line).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::config::{Config, Lid};
+use crate::netlink::utils as ev_utils;
+use crate::notif::{self, Notification, Timeout};
+use crate::Module;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+
+const EV_SW: u16 = 0x05;
+const SW_LID: u16 = 0x00;
+
+// EVIOCGSW(len), _IOC(_IOC_READ, 'E', 0x1b, len)
+const fn eviocgsw(len: usize) -> u64 {
+    (2 << 30) | ((len as u64) << 16) | ((b'E' as u64) << 8) | 0x1b
+}
+
+enum Switch {
+    // /dev/input/event*, blocks until lid moves
+    Evdev(File),
+    // /proc/acpi/button/lid/*/state, has to be polled
+    Acpi(PathBuf),
+}
+
+impl Switch {
+    fn find() -> Option<Self> {
+        let evdev = ev_utils::sysfs_devices("input")
+            .into_iter()
+            .filter(|device| {
+                device
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("event"))
+            })
+            .find(|device| {
+                // hex words, the lowest one is the last
+                std::fs::read_to_string(device.join("device/capabilities/sw"))
+                    .ok()
+                    .and_then(|sw| u64::from_str_radix(sw.split_whitespace().last()?, 16).ok())
+                    .is_some_and(|sw| sw & (1 << SW_LID) != 0)
+            });
+
+        if let Some(device) = evdev {
+            let path = PathBuf::from("/dev/input").join(device.file_name()?);
+
+            match File::open(&path) {
+                Ok(file) => return Some(Switch::Evdev(file)),
+                // usually only root and input group can read input devices
+                Err(err) => info!("{}: {err}, falling back to /proc/acpi", path.display()),
+            }
+        }
+
+        let mut lids = std::fs::read_dir("/proc/acpi/button/lid")
+            .map(|dir| dir.flatten().map(|entry| entry.path()).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        lids.sort();
+        lids.into_iter()
+            .map(|lid| lid.join("state"))
+            .find(|state| state.exists())
+            .map(Switch::Acpi)
+    }
+
+    fn closed(&self) -> Option<bool> {
+        match self {
+            Switch::Evdev(file) => {
+                let mut state = [0u8; 8];
+                let res = unsafe {
+                    libc::ioctl(
+                        file.as_raw_fd(),
+                        eviocgsw(state.len()) as _,
+                        state.as_mut_ptr(),
+                    )
+                };
+
+                (res >= 0).then(|| state[0] & (1 << SW_LID) != 0)
+            }
+            // "state:      open"
+            Switch::Acpi(path) => std::fs::read_to_string(path)
+                .ok()
+                .map(|state| state.contains("closed")),
+        }
+    }
+
+    // Ok(None) when nothing happened to the lid, e.g. other switch or poll timeout
+    fn wait(&mut self, lid_config: &Lid) -> std::io::Result<Option<bool>> {
+        match self {
+            Switch::Evdev(file) => {
+                let size = size_of::<libc::input_event>();
+                let mut buf = vec![0u8; size * 16];
+                let read = file.read(&mut buf)?;
+
+                Ok(buf[..read]
+                    .chunks_exact(size)
+                    .map(|chunk| unsafe {
+                        std::ptr::read_unaligned(chunk.as_ptr() as *const libc::input_event)
+                    })
+                    .rfind(|event| event.type_ == EV_SW && event.code == SW_LID)
+                    .map(|event| event.value != 0))
+            }
+            Switch::Acpi(_) => {
+                // unlike thread::sleep it returns early when routine is interrupted
+                match unsafe { libc::poll(std::ptr::null_mut(), 0, lid_config.poll_timeout as i32) }
+                {
+                    -1 => Err(std::io::Error::last_os_error()),
+                    _ => Ok(self.closed()),
+                }
+            }
+        }
+    }
+}
+
+fn show(notif: &mut Notification, lid_config: &Lid, closed: bool) {
+    let (status, message, icon) = if closed {
+        (
+            "closed",
+            &lid_config.closed_message,
+            &lid_config.closed_icon,
+        )
+    } else {
+        ("open", &lid_config.open_message, &lid_config.open_icon)
+    };
+    let values = [("name", "Lid"), ("status", status)];
+
+    notif
+        .app_name(&notif::app_name(&lid_config.app_name))
+        .summary(&lid_config.format.summary("Lid", &values))
+        .body(&lid_config.format.body(message, &values))
+        .urgency(lid_config.format.urgency())
+        .timeout(lid_config.format.timeout(Timeout::Millis(3000)))
+        .icon_in(&lid_config.icon_path, icon)
+        .show();
+}
+
+pub fn icons() -> Vec<String> {
+    let lid_config = &Config::get().lid;
+
+    [&lid_config.open_icon, &lid_config.closed_icon]
+        .into_iter()
+        .map(|icon| notif::join_icon(&lid_config.icon_path, icon))
+        .collect()
+}
+
+pub fn test_notification() -> Notification {
+    let config = Config::get();
+    let lid_config = &config.lid;
+    let mut notif = Notification::new();
+
+    notif
+        .module(Module::Lid)
+        .app_name(&notif::app_name(&lid_config.app_name))
+        .summary("Lid")
+        .body(&lid_config.open_message)
+        .urgency(lid_config.format.urgency())
+        .timeout(lid_config.format.timeout(Timeout::Millis(3000)))
+        .icon_in(&lid_config.icon_path, &lid_config.open_icon);
+
+    notif
+}
+
+pub fn routine() -> impl crate::Routine {
+    || {
+        let Some(mut switch) = Switch::find() else {
+            info!("no lid switch found, lid module is not running");
+            return;
+        };
+        let mut closed = switch.closed();
+        let mut notif = Notification::new();
+
+        notif.module(Module::Lid).replace_tag("sun-lid");
+
+        loop {
+            let config = Config::get();
+            let lid_config = &config.lid;
+
+            if lid_config.off || crate::shutting_down() {
+                break;
+            }
+
+            if crate::take_refresh(Module::Lid) {
+                if let Some(closed) = closed {
+                    show(&mut notif, lid_config, closed);
+                }
+            }
+
+            crate::set_busy(Module::Lid, false);
+            let res = switch.wait(lid_config);
+            crate::set_busy(Module::Lid, true);
+
+            match res {
+                Ok(Some(now_closed)) if closed != Some(now_closed) => {
+                    closed = Some(now_closed);
+                    show(&mut notif, lid_config, now_closed);
+                }
+                Ok(_) => (),
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => panic!("{err}"),
+            }
+        }
+    }
+}
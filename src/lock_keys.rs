@@ -0,0 +1,249 @@
+use crate::config::{Config, LockKeys};
+use crate::netlink::utils as ev_utils;
+use crate::netlink::{NetlinkError, NetlinkHandle};
+use crate::notif::{self, Notification, Timeout};
+use crate::Module;
+use std::io::ErrorKind;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Key {
+    Caps,
+    Num,
+    Scroll,
+}
+
+impl Key {
+    const ALL: [Key; 3] = [Key::Caps, Key::Num, Key::Scroll];
+
+    // suffix of /sys/class/leds/input*::<led>
+    fn led(self) -> &'static str {
+        match self {
+            Key::Caps => "capslock",
+            Key::Num => "numlock",
+            Key::Scroll => "scrolllock",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Key::Caps => "Caps Lock",
+            Key::Num => "Num Lock",
+            Key::Scroll => "Scroll Lock",
+        }
+    }
+
+    fn icon(self, lock_keys_config: &LockKeys) -> &str {
+        match self {
+            Key::Caps => &lock_keys_config.caps_lock_icon,
+            Key::Num => &lock_keys_config.num_lock_icon,
+            Key::Scroll => &lock_keys_config.scroll_lock_icon,
+        }
+    }
+}
+
+struct Lock {
+    key: Key,
+    on: Option<bool>,
+    notif: Notification,
+}
+
+fn watched_keys(lock_keys_config: &LockKeys) -> Vec<Key> {
+    Key::ALL
+        .into_iter()
+        .filter(|key| {
+            lock_keys_config.keys.is_empty()
+                || lock_keys_config.keys.iter().any(|name| name == key.led())
+        })
+        .collect()
+}
+
+// other names would silently match nothing
+fn warn_unknown_keys(lock_keys_config: &LockKeys) {
+    for name in lock_keys_config.keys.iter() {
+        if !Key::ALL.iter().any(|key| key.led() == name) {
+            warn!("unknown lock key {name}, expected capslock, numlock or scrolllock");
+        }
+    }
+}
+
+fn new_lock(key: Key) -> Lock {
+    let mut notif = Notification::new();
+
+    notif
+        .module(Module::LockKeys)
+        .replace_tag(&format!("sun-lock-keys-{}", key.led()));
+
+    Lock {
+        key,
+        on: read_state(key),
+        notif,
+    }
+}
+
+// every keyboard has own leds, lock is on when any of them is lit, None without keyboards
+fn read_state(key: Key) -> Option<bool> {
+    let suffix = format!("::{}", key.led());
+    let states = ev_utils::sysfs_devices("leds")
+        .into_iter()
+        .filter(|device| {
+            device
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("input") && name.ends_with(&suffix))
+        })
+        .filter_map(|device| std::fs::read_to_string(device.join("brightness")).ok())
+        .map(|brightness| brightness.trim() != "0")
+        .collect::<Vec<_>>();
+
+    (!states.is_empty()).then(|| states.contains(&true))
+}
+
+fn show(lock: &mut Lock, lock_keys_config: &LockKeys, on: bool) {
+    let name = lock.key.name();
+    let status = if on { "on" } else { "off" };
+    let values = [("name", name), ("status", status)];
+
+    if lock_keys_config.persistent && !on {
+        lock.notif.close();
+        return;
+    }
+
+    let timeout = if lock_keys_config.persistent {
+        Timeout::Never
+    } else {
+        lock_keys_config.format.timeout(Timeout::Millis(2500))
+    };
+
+    lock.notif
+        .app_name(&notif::app_name(&lock_keys_config.app_name))
+        .summary(
+            &lock_keys_config
+                .format
+                .summary(&format!("{name} {status}"), &values),
+        )
+        .body(&lock_keys_config.format.body("", &values))
+        .urgency(lock_keys_config.format.urgency())
+        .timeout(timeout)
+        .icon_in(&lock_keys_config.icon_path, lock.key.icon(lock_keys_config))
+        .show();
+}
+
+pub fn icons() -> Vec<String> {
+    let lock_keys_config = &Config::get().lock_keys;
+
+    watched_keys(lock_keys_config)
+        .into_iter()
+        .map(|key| notif::join_icon(&lock_keys_config.icon_path, key.icon(lock_keys_config)))
+        .collect()
+}
+
+pub fn test_notification() -> Notification {
+    let config = Config::get();
+    let lock_keys_config = &config.lock_keys;
+    let mut notif = Notification::new();
+
+    notif
+        .module(Module::LockKeys)
+        .app_name(&notif::app_name(&lock_keys_config.app_name))
+        .summary("Caps Lock on")
+        .urgency(lock_keys_config.format.urgency())
+        .timeout(lock_keys_config.format.timeout(Timeout::Millis(2500)))
+        .icon_in(
+            &lock_keys_config.icon_path,
+            &lock_keys_config.caps_lock_icon,
+        );
+
+    notif
+}
+
+pub fn routine() -> impl crate::Routine {
+    || {
+        let config = Config::get();
+        let mut applied_keys = config.lock_keys.keys.clone();
+        let keys = watched_keys(&config.lock_keys);
+
+        warn_unknown_keys(&config.lock_keys);
+
+        // keyboards may be plugged in later, so only a missing leds class stops the module
+        if keys.is_empty() || !std::path::Path::new("/sys/class/leds").exists() {
+            info!("no keyboard leds found, lock-keys module is not running");
+            return;
+        }
+
+        let mut locks = keys.into_iter().map(new_lock).collect::<Vec<_>>();
+        let mut handle = NetlinkHandle::new().unwrap().subscribe("leds");
+
+        loop {
+            let config = Config::get();
+            let lock_keys_config = &config.lock_keys;
+
+            if lock_keys_config.off || crate::shutting_down() {
+                break;
+            }
+
+            // keys changed on reload, ones still watched keep their state
+            if lock_keys_config.keys != applied_keys {
+                applied_keys = lock_keys_config.keys.clone();
+                warn_unknown_keys(lock_keys_config);
+
+                let keys = watched_keys(lock_keys_config);
+
+                // persistent popup of key no longer watched would never be closed
+                for lock in locks.iter_mut().filter(|lock| !keys.contains(&lock.key)) {
+                    lock.notif.close();
+                }
+
+                locks.retain(|lock| keys.contains(&lock.key));
+
+                for key in keys {
+                    if !locks.iter().any(|lock| lock.key == key) {
+                        locks.push(new_lock(key));
+                    }
+                }
+
+                if locks.is_empty() {
+                    warn!("no lock key matches keys, lock-keys module is not running");
+                    break;
+                }
+            }
+
+            if crate::take_refresh(Module::LockKeys) {
+                for lock in locks.iter_mut() {
+                    if let Some(on) = lock.on {
+                        show(lock, lock_keys_config, on);
+                    }
+                }
+            }
+
+            crate::set_busy(Module::LockKeys, false);
+            let res = handle.next_event(lock_keys_config.poll_timeout as i32);
+            crate::set_busy(Module::LockKeys, true);
+
+            match res {
+                // keyboard (un)plugged or time to poll, either way every led is read
+                Ok(_) | Err(NetlinkError::Timeout) => {
+                    for lock in locks.iter_mut() {
+                        let on = read_state(lock.key);
+                        let changed = on.is_some() && lock.on.is_some() && on != lock.on;
+
+                        // state of newly plugged keyboard isn't a change
+                        if on.is_some() {
+                            lock.on = on;
+                        }
+
+                        if changed {
+                            show(lock, lock_keys_config, on.unwrap());
+                        }
+                    }
+                }
+                Err(NetlinkError::IO(ErrorKind::Interrupted)) => (),
+                Err(err) if err.is_recoverable() => {
+                    warn!("netlink socket overrun or reset, reconnecting");
+                    handle.reconnect().unwrap_or_else(|err| panic!("{err}"));
+                }
+                Err(NetlinkError::IO(kind)) => panic!("{kind:?}"),
+                Err(_) => (),
+            }
+        }
+    }
+}
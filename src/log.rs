@@ -0,0 +1,78 @@
+use crate::journal;
+use std::fmt::Arguments;
+use std::sync::LazyLock;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+// SUN_LOG=error|warn|info|debug, anything else means info
+static MAX_LEVEL: LazyLock<Level> = LazyLock::new(|| match std::env::var("SUN_LOG").as_deref() {
+    Ok("error") => Level::Error,
+    Ok("warn") => Level::Warn,
+    Ok("debug") => Level::Debug,
+    _ => Level::Info,
+});
+
+// systemd sets it for services whose stdout goes to journal,
+// there log is sent natively so every line gets its priority
+static JOURNAL: LazyLock<bool> = LazyLock::new(|| std::env::var_os("JOURNAL_STREAM").is_some());
+
+pub fn write(level: Level, args: Arguments) {
+    if level > *MAX_LEVEL {
+        return;
+    }
+
+    let (tag, priority) = match level {
+        Level::Error => ("error", journal::PRIORITY_ERR),
+        Level::Warn => ("warn", journal::PRIORITY_WARNING),
+        Level::Info => ("info", journal::PRIORITY_INFO),
+        Level::Debug => ("debug", journal::PRIORITY_DEBUG),
+    };
+
+    // stdout is still there if journald socket isn't
+    if *JOURNAL
+        && journal::send(&[
+            ("MESSAGE", &args.to_string()),
+            ("PRIORITY", &priority.to_string()),
+            ("SYSLOG_IDENTIFIER", crate::notif::APP_NAME),
+        ])
+        .is_ok()
+    {
+        return;
+    }
+
+    println!("[{tag}] {args}");
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Error, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Warn, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Info, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*))
+    };
+}
@@ -1,46 +1,212 @@
+#[macro_use]
+mod log;
 mod battery;
 mod brightness;
 mod keyboard;
+mod lid;
+mod lock_keys;
+mod network;
 #[macro_use]
 mod netlink;
 mod config;
+mod control;
+mod journal;
 mod notif;
+#[cfg(feature = "sound")]
 mod sound;
+mod thermal;
 
 use crate::config::Config;
 use crate::notif::{Notification, Timeout, Urgency};
 use knuffel::errors::Error as KnuffelError;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::os::unix::thread::JoinHandleExt;
 use std::process::exit;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::Sender;
-use std::thread::{spawn, JoinHandle};
+use std::sync::LazyLock;
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+const CONFIG_LOAD_ATTEMPTS: u32 = 4;
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+const INTERRUPT_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
 
 // workaround for type aliases, example:
 // type Routine = impl FnOnce() + Send + 'static - won't compile
+//
+// interrupt contract: on config reload routine thread gets SIGUSR1, which makes a blocking
+// syscall fail with EINTR, routine must then check its `off` (and `take_refresh`) and return
+// when turned off. Signal may also arrive outside of a syscall, so `off` has to be checked
+// before blocking again too, stopping routine keeps resending signal until it returns
 trait Routine: FnOnce() + Send + 'static {}
 
 impl<T: FnOnce() + Send + 'static> Routine for T {}
 
-#[derive(Eq, PartialEq, Hash)]
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
 pub enum Module {
     Sound,
     Battery,
     Brightness,
     Keyboard,
+    Thermal,
+    Network,
+    Lid,
+    LockKeys,
+}
+
+impl Module {
+    pub const ALL: [Module; 8] = [
+        Module::Sound,
+        Module::Battery,
+        Module::Brightness,
+        Module::Keyboard,
+        Module::Thermal,
+        Module::Network,
+        Module::Lid,
+        Module::LockKeys,
+    ];
+}
+
+impl FromStr for Module {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "sound" => Ok(Module::Sound),
+            "battery" => Ok(Module::Battery),
+            "brightness" => Ok(Module::Brightness),
+            "keyboard" => Ok(Module::Keyboard),
+            "thermal" => Ok(Module::Thermal),
+            "network" => Ok(Module::Network),
+            "lid" => Ok(Module::Lid),
+            "lock-keys" => Ok(Module::LockKeys),
+            other => Err(format!("unknown module: {other}")),
+        }
+    }
+}
+
+enum Command {
+    Run,
+    TestNotify(Module),
+    InspectUevents,
+    ValidateIcons,
+    CheckConfig,
+    DumpConfig,
+    WriteDefaultConfig,
 }
 
 pub enum Message {
     ModulePanic(String),
     ConfigReload(Config),
     ConfigReloadError(KnuffelError),
+    ShowAll,
+    ModuleStuck(Module, Duration),
+    Shutdown,
+}
+
+// --force of --write-default-config
+static FORCE: AtomicBool = AtomicBool::new(false);
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+// routines check it next to their `off`, so SIGTERM/SIGINT stop them the same way
+pub fn shutting_down() -> bool {
+    SHUTDOWN.load(Ordering::Relaxed)
+}
+
+static REFRESH: [AtomicBool; Module::ALL.len()] =
+    [const { AtomicBool::new(false) }; Module::ALL.len()];
+
+// routines call it after being interrupted to know if they should show their current state
+pub fn take_refresh(module: Module) -> bool {
+    REFRESH[module as usize].swap(false, Ordering::Relaxed)
+}
+
+// monotonic base for BUSY_SINCE, wall clock jumps would look like hangs
+static BUSY_BASE: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+// ms since BUSY_BASE plus 1, when module started handling an event, 0 while it waits for events
+static BUSY_SINCE: [AtomicU64; Module::ALL.len()] =
+    [const { AtomicU64::new(0) }; Module::ALL.len()];
+
+// waiting for events may take forever, so only time spent handling them is watched
+pub fn set_busy(module: Module, busy: bool) {
+    let now = if busy {
+        BUSY_BASE.elapsed().as_millis() as u64 + 1
+    } else {
+        0
+    };
+
+    BUSY_SINCE[module as usize].store(now, Ordering::Relaxed);
+}
+
+fn watchdog(sender: Sender<Message>) -> impl Routine {
+    move || {
+        let mut reported = HashSet::new();
+
+        loop {
+            sleep(WATCHDOG_INTERVAL);
+
+            let timeout = Duration::from_millis(Config::get().watchdog_timeout);
+
+            if timeout.is_zero() {
+                continue;
+            }
+
+            let now = BUSY_BASE.elapsed();
+
+            for module in Module::ALL {
+                let since = BUSY_SINCE[module as usize].load(Ordering::Relaxed);
+                let busy_for = now.saturating_sub(Duration::from_millis(since.saturating_sub(1)));
+
+                // same hang is reported only once
+                if since != 0 && busy_for > timeout && reported.insert((module, since)) {
+                    sender.send(Message::ModuleStuck(module, busy_for)).unwrap();
+                }
+            }
+        }
+    }
 }
 
 extern "C" fn sa_action(_: libc::c_int) {}
 
-fn setup_sigaction(sender: Sender<Message>) {
+// blocked in every thread spawned afterwards, only signal thread of setup_sigaction gets them
+fn block_shutdown_signals() -> libc::sigset_t {
+    unsafe {
+        let mut shutdown_set = std::mem::zeroed::<libc::sigset_t>();
+
+        libc::sigemptyset(&mut shutdown_set);
+        libc::sigaddset(&mut shutdown_set, libc::SIGTERM);
+        libc::sigaddset(&mut shutdown_set, libc::SIGINT);
+
+        match libc::pthread_sigmask(libc::SIG_BLOCK, &shutdown_set, std::ptr::null_mut()) {
+            0 => (),
+            err => panic!(
+                "pthread_sigmask error: {}",
+                std::io::Error::from_raw_os_error(err)
+            ),
+        }
+
+        shutdown_set
+    }
+}
+
+fn setup_sigaction(sender: Sender<Message>, shutdown_set: libc::sigset_t) {
     unsafe {
         let mut action = std::mem::zeroed::<libc::sigaction>();
+        let shutdown_sender = sender.clone();
+
+        spawn(move || {
+            let mut signal = 0;
+
+            if libc::sigwait(&shutdown_set, &mut signal) == 0 {
+                info!("got signal {signal}, shutting down");
+                _ = shutdown_sender.send(Message::Shutdown);
+            }
+        });
 
         action.sa_sigaction = sa_action as usize;
         action.sa_flags = libc::SA_NODEFER;
@@ -65,13 +231,13 @@ fn setup_sigaction(sender: Sender<Message>) {
                 info.location().unwrap(), // blindly believing in rust docs that it won't ever panic
                 std::backtrace::Backtrace::force_capture()
             ))) {
-                println!("mpsc sender error: {err:#?}\npayload: {p}");
+                error!("mpsc sender error: {err:#?}\npayload: {p}");
                 exit(-1);
             };
         };
 
         notif
-            .timeout(Timeout::Never)
+            .timeout(Timeout::from(config.error_timeout))
             .urgency(Urgency::Critical)
             .summary("SUN just died")
             .body("Checks logs for details")
@@ -89,45 +255,365 @@ fn setup_sigaction(sender: Sender<Message>) {
     }));
 }
 
+fn interrupt_routine(handle: &JoinHandle<()>) {
+    // ESRCH - thread has already exited, nothing to interrupt
+    match unsafe { libc::pthread_kill(handle.as_pthread_t(), libc::SIGUSR1) } {
+        0 | libc::ESRCH => (),
+        err => {
+            error!(
+                "pthread_kill error: {}",
+                std::io::Error::from_raw_os_error(err)
+            );
+            exit(-1);
+        }
+    }
+}
+
+// signal could land right before routine blocked again, so keep poking until it returns
+fn stop_routine(handle: JoinHandle<()>) {
+    while !handle.is_finished() {
+        interrupt_routine(&handle);
+        sleep(INTERRUPT_RETRY_INTERVAL);
+    }
+
+    // panicked routine is already reported by panic hook
+    _ = handle.join();
+}
+
 fn update_routine(
     name: Module,
     routines: &mut HashMap<Module, JoinHandle<()>>,
     off: bool,
     routine: impl Routine,
 ) {
-    if let Some(handle) = routines.get_mut(&name) {
-        unsafe {
-            if libc::pthread_kill(handle.as_pthread_t(), libc::SIGUSR1) != 0 {
-                println!("{}", errno_msg!("pthread_kill error"));
-                exit(-1);
-            }
-        }
+    // routine may return by itself, e.g. when there is no device to watch
+    if routines
+        .get(&name)
+        .is_some_and(|handle| handle.is_finished())
+    {
+        _ = routines.remove(&name).unwrap().join();
+    }
 
+    if let Some(handle) = routines.get_mut(&name) {
         if off {
-            routines.remove(&name).unwrap().join().unwrap();
+            stop_routine(routines.remove(&name).unwrap());
+        } else {
+            interrupt_routine(handle);
         }
     } else {
         if !off {
-            routines.insert(name, spawn(routine));
+            routines.insert(
+                name,
+                spawn(move || {
+                    routine();
+                    set_busy(name, false);
+                }),
+            );
+        }
+    }
+}
+
+// config may be caught in the middle of writing on autostart, so give it a few tries
+fn load_config(sender: &Sender<Message>) -> Config {
+    let mut delay = Duration::from_millis(250);
+
+    for attempt in 1.. {
+        match Config::update() {
+            Ok(config) => return config,
+            Err(err) if attempt == CONFIG_LOAD_ATTEMPTS => {
+                warn!("config load failed {attempt} times, starting with defaults");
+                sender.send(Message::ConfigReloadError(err)).unwrap();
+                break;
+            }
+            Err(_) => {
+                sleep(delay);
+                delay *= 2;
+            }
+        }
+    }
+
+    Config::fallback()
+}
+
+fn module_status(off: bool) -> &'static str {
+    if off {
+        "off"
+    } else {
+        "on"
+    }
+}
+
+fn parse_args() -> Command {
+    let mut args = std::env::args().skip(1);
+    let mut command = Command::Run;
+    let mut force = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--test-notify" => match args.next().map(|name| Module::from_str(&name)) {
+                Some(Ok(module)) => command = Command::TestNotify(module),
+                Some(Err(err)) => {
+                    println!("{err}");
+                    usage();
+                }
+                None => usage(),
+            },
+            "--inspect-uevents" => command = Command::InspectUevents,
+            "--validate-icons" => command = Command::ValidateIcons,
+            "--check-config" => command = Command::CheckConfig,
+            "--dump-config" => command = Command::DumpConfig,
+            "--write-default-config" => command = Command::WriteDefaultConfig,
+            "--force" => force = true,
+            "--config" => match args.next().as_deref() {
+                Some("-") => config::Source::stdin()
+                    .unwrap_or_else(|err| panic!("failed to read config from stdin: {err}"))
+                    .set(),
+                Some(path) => config::Source::File(path.into()).set(),
+                None => usage(),
+            },
+            _ => usage(),
+        }
+    }
+
+    if force && !matches!(command, Command::WriteDefaultConfig) {
+        usage();
+    }
+
+    FORCE.store(force, Ordering::Relaxed);
+    command
+}
+
+fn usage() -> ! {
+    println!(
+        "usage: sun [--config <path|->] [--test-notify <sound|battery|brightness|keyboard|thermal|network|lid|lock-keys>] [--inspect-uevents] [--validate-icons] [--check-config] [--dump-config] [--write-default-config [--force]]"
+    );
+    exit(1);
+}
+
+pub fn test_notification(module: Module) -> Notification {
+    match module {
+        #[cfg(feature = "sound")]
+        Module::Sound => sound::test_notification(),
+        #[cfg(not(feature = "sound"))]
+        Module::Sound => {
+            let mut notif = Notification::new();
+            notif.summary("Sound").body("Built without sound support");
+            notif
+        }
+        Module::Battery => battery::test_notification(),
+        Module::Brightness => brightness::test_notification(),
+        Module::Keyboard => keyboard::test_notification(),
+        Module::Thermal => thermal::test_notification(),
+        Module::Network => network::test_notification(),
+        Module::Lid => lid::test_notification(),
+        Module::LockKeys => lock_keys::test_notification(),
+    }
+}
+
+// one-shot commands don't start anything with broken config
+fn update_config_or_exit() -> Config {
+    match Config::update() {
+        Ok(config) => config,
+        Err(err) => {
+            println!("config parse error:\n{}", config::describe_error(&err));
+            exit(1);
+        }
+    }
+}
+
+fn check_config() -> ! {
+    update_config_or_exit();
+    println!("{}: ok", config::Source::get());
+    exit(0);
+}
+
+// every field, including defaults which aren't in config file
+fn dump_config() -> ! {
+    let config = update_config_or_exit();
+
+    println!("{}", serde_json::to_string_pretty(&config).unwrap());
+    exit(0);
+}
+
+fn write_default_config() -> ! {
+    let config::Source::File(path) = config::Source::get() else {
+        println!("config is read from stdin, there is nowhere to write it");
+        exit(1);
+    };
+
+    if path.exists() && !FORCE.load(Ordering::Relaxed) {
+        println!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        );
+        exit(1);
+    }
+
+    let kdl = config::default_kdl();
+    let written = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(path, kdl));
+
+    if let Err(err) = written {
+        println!("failed to write {}: {err}", path.display());
+        exit(1);
+    }
+
+    // written file has to be accepted by the same parser
+    update_config_or_exit();
+    println!("default config written to {}", path.display());
+    exit(0);
+}
+
+fn test_notify(module: Module) -> ! {
+    update_config_or_exit();
+
+    test_notification(module).show();
+    exit(0);
+}
+
+// bare icon names are resolved by notification server, so only files are checked
+fn validate_icons() -> ! {
+    update_config_or_exit();
+
+    #[cfg(feature = "sound")]
+    let sound_icons = sound::icons();
+    #[cfg(not(feature = "sound"))]
+    let sound_icons = Vec::new();
+
+    let config = Config::get();
+    let sun_icons = [Some(&config.error_icon), config.fallback_icon.as_ref()]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let modules = [
+        ("sun", sun_icons),
+        ("sound", sound_icons),
+        ("battery", battery::icons()),
+        ("brightness", brightness::icons()),
+        ("keyboard", keyboard::icons()),
+        ("thermal", thermal::icons()),
+        ("network", network::icons()),
+        ("lid", lid::icons()),
+        ("lock-keys", lock_keys::icons()),
+    ];
+    let mut missing = 0;
+
+    for (module, icons) in modules {
+        for icon in icons {
+            if notif::icon_missing(&icon) {
+                let path = icon.strip_prefix("file://").unwrap_or(&icon);
+
+                println!("{module}: missing {path}");
+                missing += 1;
+            }
+        }
+    }
+
+    if missing > 0 {
+        println!("{missing} icon(s) missing");
+        exit(1);
+    }
+
+    println!("all icons found");
+    exit(0);
+}
+
+// prints everything battery and brightness modules could see, until killed
+fn inspect_uevents() -> ! {
+    let mut handle = netlink::NetlinkHandle::new().unwrap_or_else(|err| panic!("{err}"));
+
+    loop {
+        match handle.read_uevent::<netlink::RawUevent, String>() {
+            Ok(ev) => {
+                let get = |name| {
+                    ev.fields
+                        .iter()
+                        .find(|(key, _)| key == name)
+                        .map_or("?", |(_, val)| val.as_str())
+                };
+
+                println!("{} {} {}", get("SUBSYSTEM"), get("ACTION"), ev.header);
+
+                for (key, val) in ev.fields.iter() {
+                    println!("    {key}={val}");
+                }
+            }
+            Err(netlink::NetlinkError::IO(kind)) => panic!("{kind:?}"),
+            Err(netlink::NetlinkError::Serialize(err)) => println!("malformed uevent: {err}"),
+            Err(netlink::NetlinkError::Timeout) => (),
+            Err(netlink::NetlinkError::Overrun) => println!("uevents overrun, some were dropped"),
         }
     }
 }
 
 fn main() {
+    match parse_args() {
+        Command::TestNotify(module) => test_notify(module),
+        Command::InspectUevents => inspect_uevents(),
+        Command::ValidateIcons => validate_icons(),
+        Command::CheckConfig => check_config(),
+        Command::DumpConfig => dump_config(),
+        Command::WriteDefaultConfig => write_default_config(),
+        Command::Run => (),
+    }
+
+    // before anything spawns threads, e.g. config watcher of load_config, so none of them
+    // inherits unblocked mask and steals the signal, subcommands above keep default ctrl-c
+    let shutdown_set = block_shutdown_signals();
+
+    LazyLock::force(&BUSY_BASE);
+
     let (sender, reciever) = std::sync::mpsc::channel::<Message>();
     let mut routines = HashMap::new();
 
-    sender
-        .send(Message::ConfigReload(Config::update().unwrap()))
-        .unwrap();
+    let config = load_config(&sender);
+
+    info!(
+        "sun {} started: config={} sound={} battery={} keyboard={} brightness={} thermal={} network={} lid={} lock-keys={}",
+        env!("CARGO_PKG_VERSION"),
+        config::Source::get(),
+        module_status(config.sound.off || !cfg!(feature = "sound")),
+        module_status(config.battery.off),
+        module_status(config.keyboard.off),
+        module_status(config.brightness.off),
+        module_status(config.thermal.off),
+        module_status(config.network.off),
+        module_status(config.lid.off),
+        module_status(config.lock_keys.off),
+    );
+
+    // control interface and config reload keep working without any module
+    if config.sound.off
+        && config.battery.off
+        && config.keyboard.off
+        && config.brightness.off
+        && config.thermal.off
+        && config.network.off
+        && config.lid.off
+        && config.lock_keys.off
+    {
+        info!("all modules are off, running in control-only mode");
+    }
+
+    sender.send(Message::ConfigReload(config)).unwrap();
 
-    setup_sigaction(sender.clone());
+    setup_sigaction(sender.clone(), shutdown_set);
 
-    spawn(config::routine(sender));
+    spawn(config::routine(sender.clone()));
+    spawn(watchdog(sender.clone()));
+    spawn(control::routine(sender));
 
     loop {
         match reciever.recv() {
             Ok(Message::ConfigReload(config)) => {
+                debug!("applying config from {}", config::Source::get());
+
+                // config section is still parsed, there is just nothing to run
+                #[cfg(feature = "sound")]
                 update_routine(
                     Module::Sound,
                     &mut routines,
@@ -152,19 +638,76 @@ fn main() {
                     config.brightness.off,
                     brightness::routine(),
                 );
+                update_routine(
+                    Module::Thermal,
+                    &mut routines,
+                    config.thermal.off,
+                    thermal::routine(),
+                );
+                update_routine(
+                    Module::Network,
+                    &mut routines,
+                    config.network.off,
+                    network::routine(),
+                );
+                update_routine(Module::Lid, &mut routines, config.lid.off, lid::routine());
+                update_routine(
+                    Module::LockKeys,
+                    &mut routines,
+                    config.lock_keys.off,
+                    lock_keys::routine(),
+                );
             }
             Ok(Message::ConfigReloadError(err)) => {
                 Notification::new()
                     .summary("SUN failed to parse config")
                     .body("Check logs for details")
                     .urgency(Urgency::Critical)
-                    .timeout(Timeout::Never)
+                    .timeout(Timeout::from(Config::get().error_timeout))
                     .icon(&Config::get().error_icon)
                     .show();
-                println!("config parse error:\n{err:#?}");
+                error!("config parse error:\n{}", config::describe_error(&err));
+            }
+            Ok(Message::ShowAll) => {
+                for (module, handle) in routines.iter() {
+                    REFRESH[*module as usize].store(true, Ordering::Relaxed);
+                    interrupt_routine(handle);
+                }
+            }
+            Ok(Message::ModuleStuck(module, busy_for)) => {
+                error!("{module:?} module seems to be stuck for {busy_for:?}");
+
+                // there is no way to kill a thread, so let service manager restart whole app
+                if Config::get().watchdog_exit {
+                    Notification::new()
+                        .summary("SUN module got stuck")
+                        .body("Check logs for details")
+                        .urgency(Urgency::Critical)
+                        .timeout(Timeout::from(Config::get().error_timeout))
+                        .icon(&Config::get().error_icon)
+                        .show();
+                    exit(-1);
+                }
+            }
+            Ok(Message::Shutdown) => {
+                SHUTDOWN.store(true, Ordering::Relaxed);
+
+                // module stuck in uninterruptible call shouldn't keep service from stopping
+                spawn(|| {
+                    sleep(SHUTDOWN_TIMEOUT);
+                    warn!("modules didn't stop in {SHUTDOWN_TIMEOUT:?}, exiting anyway");
+                    exit(0);
+                });
+
+                for (_, handle) in routines.drain() {
+                    stop_routine(handle);
+                }
+
+                notif::close_all();
+                exit(0);
             }
             Ok(Message::ModulePanic(payload)) => {
-                println!("{payload}");
+                error!("{payload}");
                 break;
             }
             Err(err) => panic!("mpsc reciever error:\n{err:#?}"),
@@ -1,6 +1,10 @@
 use libc;
 use std::io::Error;
 use std::mem::zeroed;
+use std::time::{Duration, Instant};
+
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+const RECONNECT_RESET: Duration = Duration::from_secs(60);
 
 #[macro_export]
 macro_rules! errno_msg {
@@ -16,6 +20,18 @@ macro_rules! errno_msg {
 }
 
 pub mod utils {
+    use std::path::PathBuf;
+
+    // devices of sysfs class (e.g. "backlight"), sorted so pick is the same on every run
+    pub fn sysfs_devices(class: &str) -> Vec<PathBuf> {
+        let mut devices = std::fs::read_dir(format!("/sys/class/{class}"))
+            .map(|dir| dir.flatten().map(|entry| entry.path()).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        devices.sort();
+        devices
+    }
+
     pub fn get_element_val(uevent_str: &str, name: &str) -> Option<String> {
         let delim = if uevent_str.contains("\0") {
             "\0"
@@ -39,6 +55,27 @@ pub enum NetlinkError<E> {
     Timeout,
     IO(std::io::ErrorKind),
     Serialize(E),
+    // ENOBUFS, kernel dropped uevents we didn't read in time
+    Overrun,
+}
+
+impl<E> NetlinkError<E> {
+    fn last_os_error() -> Self {
+        let err = Error::last_os_error();
+
+        match err.raw_os_error() {
+            Some(libc::ENOBUFS) => NetlinkError::Overrun,
+            _ => NetlinkError::IO(err.kind()),
+        }
+    }
+
+    // socket is fine after reconnect, e.g. after burst of uevents
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            NetlinkError::Overrun | NetlinkError::IO(std::io::ErrorKind::ConnectionReset)
+        )
+    }
 }
 
 pub trait Uevent<E> {
@@ -47,19 +84,112 @@ pub trait Uevent<E> {
         Self: Sized;
 }
 
+// whole uevent as is, used for debugging
+pub struct RawUevent {
+    pub header: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl Uevent<String> for RawUevent {
+    fn from_bytes(data: &Vec<u8>) -> Result<Self, String> {
+        let uevent_str = String::from_utf8_lossy(data);
+        let mut parts = uevent_str.split('\0').filter(|part| !part.is_empty());
+        let header = parts.next().ok_or("empty uevent".to_owned())?.to_owned();
+
+        Ok(Self {
+            header,
+            fields: parts
+                .filter_map(|part| part.split_once('='))
+                .map(|(key, val)| (key.to_owned(), val.to_owned()))
+                .collect(),
+        })
+    }
+}
+
+// uevent fields looked up lazily, for modules which don't need their own Uevent impl
+pub struct UeventFields {
+    data: String,
+}
+
+impl UeventFields {
+    pub fn get(&self, name: &str) -> Option<String> {
+        utils::get_element_val(&self.data, name)
+    }
+}
+
+impl Uevent<String> for UeventFields {
+    fn from_bytes(data: &Vec<u8>) -> Result<Self, String> {
+        Ok(Self {
+            data: String::from_utf8_lossy(data).into_owned(),
+        })
+    }
+}
+
 pub struct NetlinkHandle {
     fd: i32,
+    protocol: i32,
+    groups: u32,
     buf: Vec<u8>,
+    // empty means every subsystem
+    subsystems: Vec<String>,
+    reconnects: u32,
+    reconnected_at: Option<Instant>,
+}
+
+impl Drop for NetlinkHandle {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
 }
 
 impl NetlinkHandle {
+    // kernel uevents
     pub fn new() -> Result<Self, String> {
+        Self::with_protocol(libc::NETLINK_KOBJECT_UEVENT, 1)
+    }
+
+    // e.g. NETLINK_ROUTE with RTMGRP_LINK, messages are passed to Uevent::from_bytes as is
+    pub fn with_protocol(protocol: i32, groups: u32) -> Result<Self, String> {
+        Ok(Self {
+            fd: Self::open(protocol, groups)?,
+            protocol,
+            groups,
+            buf: Vec::with_capacity(256),
+            subsystems: Vec::new(),
+            reconnects: 0,
+            reconnected_at: None,
+        })
+    }
+
+    // backoff doubles on every reconnect in a row, so broken socket doesn't spin
+    pub fn reconnect(&mut self) -> Result<(), String> {
+        if self
+            .reconnected_at
+            .is_some_and(|at| at.elapsed() > RECONNECT_RESET)
+        {
+            self.reconnects = 0;
+        }
+
+        let backoff = RECONNECT_BACKOFF * 2u32.pow(std::cmp::min(self.reconnects, 6));
+
+        std::thread::sleep(backoff);
+
         unsafe {
-            let fd = libc::socket(
-                libc::AF_NETLINK,
-                libc::SOCK_RAW,
-                libc::NETLINK_KOBJECT_UEVENT,
-            );
+            libc::close(self.fd);
+        }
+
+        self.fd = Self::open(self.protocol, self.groups)?;
+        self.reconnects += 1;
+        self.reconnected_at = Some(Instant::now());
+
+        Ok(())
+    }
+
+    fn open(protocol: i32, groups: u32) -> Result<i32, String> {
+        unsafe {
+            let fd = libc::socket(libc::AF_NETLINK, libc::SOCK_RAW, protocol);
 
             if fd == -1 {
                 return Err(errno_msg!("libc::socket error"));
@@ -68,7 +198,7 @@ impl NetlinkHandle {
             let mut addr = zeroed::<libc::sockaddr_nl>();
 
             addr.nl_family = libc::AF_NETLINK as u16;
-            addr.nl_groups = 1;
+            addr.nl_groups = groups;
 
             if libc::bind(
                 fd,
@@ -76,20 +206,62 @@ impl NetlinkHandle {
                 size_of::<libc::sockaddr_nl>() as u32,
             ) == -1
             {
-                return Err(errno_msg!("libc::bind error"));
+                let err = errno_msg!("libc::bind error");
+
+                libc::close(fd);
+                return Err(err);
             }
 
-            Ok(Self {
-                fd,
-                buf: Vec::with_capacity(256),
-            })
+            Ok(fd)
         }
     }
 
+    // uevents of other subsystems are dropped before parsing, can be called several times
+    pub fn subscribe(mut self, subsystem: &str) -> Self {
+        self.subsystems.push(subsystem.into());
+        self
+    }
+
+    pub fn next_event(&mut self, timeout: i32) -> Result<UeventFields, NetlinkError<String>> {
+        self.read_uevent_msec(timeout)
+    }
+
+    fn is_subscribed(&self) -> bool {
+        if self.subsystems.is_empty() {
+            return true;
+        }
+
+        let uevent_str = String::from_utf8_lossy(&self.buf);
+
+        utils::get_element_val(&uevent_str, "SUBSYSTEM")
+            .is_some_and(|subsystem| self.subsystems.contains(&subsystem))
+    }
+
     pub fn read_uevent_msec<U: Uevent<E>, E>(
         &mut self,
         timeout: i32,
     ) -> Result<U, NetlinkError<E>> {
+        let deadline =
+            (timeout > 0).then(|| Instant::now() + Duration::from_millis(timeout as u64));
+
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => match deadline.saturating_duration_since(Instant::now()) {
+                    left if left.is_zero() => return Err(NetlinkError::Timeout),
+                    left => std::cmp::max(left.as_millis() as i32, 1),
+                },
+                None => timeout,
+            };
+
+            self.recv_msec(timeout)?;
+
+            if self.is_subscribed() {
+                return U::from_bytes(&self.buf).map_err(|e| NetlinkError::Serialize(e));
+            }
+        }
+    }
+
+    fn recv_msec<E>(&mut self, timeout: i32) -> Result<(), NetlinkError<E>> {
         unsafe {
             let mut header = zeroed::<libc::msghdr>();
             let mut iov = zeroed::<libc::iovec>();
@@ -116,7 +288,7 @@ impl NetlinkHandle {
                         return Err(NetlinkError::Timeout);
                     }
                     i if i == -1 => {
-                        return Err(NetlinkError::IO(Error::last_os_error().kind()));
+                        return Err(NetlinkError::last_os_error());
                     }
                     _ => (), // ready to read
                 }
@@ -124,7 +296,7 @@ impl NetlinkHandle {
 
             loop {
                 match libc::recvmsg(self.fd, &mut header, flags) {
-                    i if i == -1 => return Err(NetlinkError::IO(Error::last_os_error().kind())),
+                    i if i == -1 => return Err(NetlinkError::last_os_error()),
                     i => {
                         if i > self.buf.capacity() as isize {
                             self.buf.resize(i as usize * 2, 0);
@@ -138,8 +310,7 @@ impl NetlinkHandle {
                         if flags & libc::MSG_DONTWAIT == 0 {
                             flags ^= libc::MSG_PEEK | libc::MSG_TRUNC | libc::MSG_DONTWAIT;
                         } else {
-                            return U::from_bytes(&self.buf)
-                                .map_err(|e| NetlinkError::Serialize(e));
+                            return Ok(());
                         }
                     }
                 }
@@ -0,0 +1,325 @@
+use crate::config::{Config, Network};
+use crate::netlink::utils as ev_utils;
+use crate::netlink::{NetlinkError, NetlinkHandle, Uevent};
+use crate::notif::{self, Notification, Timeout};
+use crate::Module;
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::Path;
+
+const NLMSG_HEADER_LEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+const RTATTR_HEADER_LEN: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct LinkState {
+    up: bool,
+    carrier: bool,
+}
+
+// RTM_NEWLINK/RTM_DELLINK messages of a single datagram
+struct LinkMessages(Vec<(String, LinkState)>);
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// nlmsghdr, then ifinfomsg followed by rtattrs, only IFLA_IFNAME is needed
+fn parse_link(message: &[u8], deleted: bool) -> Option<(String, LinkState)> {
+    let flags = read_u32(message, NLMSG_HEADER_LEN + 8)?;
+    let mut offset = NLMSG_HEADER_LEN + IFINFOMSG_LEN;
+
+    while offset + RTATTR_HEADER_LEN <= message.len() {
+        let len = read_u16(message, offset)? as usize;
+        let kind = read_u16(message, offset + 2)?;
+
+        if len < RTATTR_HEADER_LEN {
+            return None;
+        }
+
+        if kind == libc::IFLA_IFNAME {
+            let name = message.get(offset + RTATTR_HEADER_LEN..offset + len)?;
+            let name = String::from_utf8_lossy(name)
+                .trim_end_matches('\0')
+                .to_owned();
+            let state = LinkState {
+                up: !deleted && flags & libc::IFF_UP as u32 != 0,
+                carrier: !deleted && flags & libc::IFF_LOWER_UP as u32 != 0,
+            };
+
+            return Some((name, state));
+        }
+
+        offset += align(len);
+    }
+
+    None
+}
+
+impl Uevent<String> for LinkMessages {
+    fn from_bytes(data: &Vec<u8>) -> Result<Self, String> {
+        let mut links = Vec::new();
+        let mut offset = 0;
+
+        while offset + NLMSG_HEADER_LEN <= data.len() {
+            let len = read_u32(data, offset).ok_or("truncated nlmsghdr")? as usize;
+            let kind = read_u16(data, offset + 4).ok_or("truncated nlmsghdr")?;
+            let message = data
+                .get(offset..offset + len)
+                .filter(|_| len >= NLMSG_HEADER_LEN)
+                .ok_or("malformed rtnetlink message")?;
+
+            match kind {
+                libc::RTM_NEWLINK => links.extend(parse_link(message, false)),
+                libc::RTM_DELLINK => links.extend(parse_link(message, true)),
+                _ => (),
+            }
+
+            offset += align(len);
+        }
+
+        Ok(Self(links))
+    }
+}
+
+fn matches(pattern: &str, interface: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => interface.starts_with(prefix),
+        None => pattern == interface,
+    }
+}
+
+fn watched(network_config: &Network, physical: &HashSet<String>, interface: &str) -> bool {
+    if interface == "lo" {
+        return false;
+    }
+
+    if !network_config.interfaces.is_empty() {
+        return network_config
+            .interfaces
+            .iter()
+            .any(|pattern| matches(pattern, interface));
+    }
+
+    if network_config
+        .ignore
+        .iter()
+        .any(|pattern| matches(pattern, interface))
+    {
+        return false;
+    }
+
+    network_config.include_virtual || physical.contains(interface)
+}
+
+// device link is removed together with interface, so for RTM_DELLINK what was seen before is used
+fn remember_physical(physical: &mut HashSet<String>, interface: &str) {
+    let device = Path::new("/sys/class/net").join(interface);
+
+    if !device.exists() {
+        return;
+    }
+
+    if device.join("device").exists() {
+        physical.insert(interface.into());
+    } else {
+        physical.remove(interface);
+    }
+}
+
+// state before first rtnetlink message, so only changes are shown
+fn read_links(physical: &mut HashSet<String>) -> HashMap<String, LinkState> {
+    ev_utils::sysfs_devices("net")
+        .into_iter()
+        .filter_map(|device| {
+            let name = device.file_name()?.to_str()?.to_owned();
+
+            remember_physical(physical, &name);
+
+            let flags = std::fs::read_to_string(device.join("flags")).ok()?;
+            let flags = u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok()?;
+            // reading carrier of interface which is down fails
+            let carrier = std::fs::read_to_string(device.join("carrier"))
+                .is_ok_and(|carrier| carrier.trim() == "1");
+
+            Some((
+                name,
+                LinkState {
+                    up: flags & libc::IFF_UP as u32 != 0,
+                    carrier,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn show(notif: &mut Notification, network_config: &Network, interface: &str, state: LinkState) {
+    let status = match (state.up, state.carrier) {
+        (true, true) => "connected",
+        (true, false) => "disconnected",
+        (false, _) => "down",
+    };
+    let values = [
+        ("name", "Network"),
+        ("status", status),
+        ("interface", interface),
+    ];
+    let icon = network_config.icon(interface, state.carrier);
+
+    notif
+        .app_name(&notif::app_name(&network_config.app_name))
+        .summary(&network_config.format.summary("Network", &values))
+        .body(
+            &network_config
+                .format
+                .body(&format!("{interface} {status}"), &values),
+        )
+        .urgency(network_config.format.urgency())
+        .timeout(network_config.format.timeout(Timeout::Millis(3000)))
+        .icon_in(&network_config.icon_path, icon)
+        .show();
+}
+
+fn notif_for<'a>(
+    notifs: &'a mut HashMap<String, Notification>,
+    interface: &str,
+) -> &'a mut Notification {
+    notifs.entry(interface.into()).or_insert_with(|| {
+        let mut notif = Notification::new();
+
+        notif
+            .module(Module::Network)
+            .replace_tag(&format!("sun-network-{interface}"));
+        notif
+    })
+}
+
+pub fn icons() -> Vec<String> {
+    let network_config = &Config::get().network;
+
+    [
+        &network_config.connected_icon,
+        &network_config.disconnected_icon,
+    ]
+    .into_iter()
+    .chain(
+        network_config
+            .interface_icons
+            .icons
+            .iter()
+            .flat_map(|icon| [&icon.connected, &icon.disconnected]),
+    )
+    .map(|icon| notif::join_icon(&network_config.icon_path, icon))
+    .collect()
+}
+
+pub fn test_notification() -> Notification {
+    let config = Config::get();
+    let network_config = &config.network;
+    let mut notif = Notification::new();
+
+    notif
+        .module(Module::Network)
+        .app_name(&notif::app_name(&network_config.app_name))
+        .summary("Network")
+        .body("eth0 connected")
+        .urgency(network_config.format.urgency())
+        .timeout(network_config.format.timeout(Timeout::Millis(3000)))
+        .icon_in(&network_config.icon_path, &network_config.connected_icon);
+
+    notif
+}
+
+pub fn routine() -> impl crate::Routine {
+    || {
+        let mut handle =
+            match NetlinkHandle::with_protocol(libc::NETLINK_ROUTE, libc::RTMGRP_LINK as u32) {
+                Ok(handle) => handle,
+                Err(err) => {
+                    warn!("{err}, network module is not running");
+                    return;
+                }
+            };
+        let mut physical = HashSet::new();
+        let mut links = read_links(&mut physical);
+        // interface's popup replaces only its own previous one
+        let mut notifs = HashMap::<String, Notification>::new();
+
+        loop {
+            let config = Config::get();
+            let network_config = &config.network;
+
+            if network_config.off || crate::shutting_down() {
+                break;
+            }
+
+            if crate::take_refresh(Module::Network) {
+                for (interface, state) in links.iter() {
+                    if watched(network_config, &physical, interface) {
+                        show(
+                            notif_for(&mut notifs, interface),
+                            network_config,
+                            interface,
+                            *state,
+                        );
+                    }
+                }
+            }
+
+            crate::set_busy(Module::Network, false);
+            let res = handle.read_uevent::<LinkMessages, String>();
+            crate::set_busy(Module::Network, true);
+
+            match res {
+                Ok(LinkMessages(messages)) => {
+                    for (interface, state) in messages {
+                        remember_physical(&mut physical, &interface);
+
+                        let previous = links.insert(interface.clone(), state);
+
+                        // e.g. flags other than up and carrier changed
+                        if previous == Some(state)
+                            || !watched(network_config, &physical, &interface)
+                        {
+                            continue;
+                        }
+
+                        // new interface is worth showing only once it's connected
+                        if previous.is_none() && !state.carrier {
+                            continue;
+                        }
+
+                        show(
+                            notif_for(&mut notifs, &interface),
+                            network_config,
+                            &interface,
+                            state,
+                        );
+                    }
+                }
+                Err(NetlinkError::IO(ErrorKind::Interrupted)) => (),
+                Err(err) if err.is_recoverable() => {
+                    warn!("netlink socket overrun or reset, reconnecting");
+                    handle.reconnect().unwrap_or_else(|err| panic!("{err}"));
+                    // changes may have been dropped with overrun
+                    links = read_links(&mut physical);
+                }
+                Err(NetlinkError::IO(kind)) => panic!("{kind:?}"),
+                Err(NetlinkError::Serialize(err)) => warn!("malformed rtnetlink message: {err}"),
+                Err(_) => (),
+            }
+        }
+    }
+}
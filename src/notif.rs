@@ -1,22 +1,341 @@
-use std::collections::HashMap;
+use crate::config::{self, Config};
+use crate::journal;
+use crate::Module;
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::{Arc, LazyLock};
+use std::sync::{Arc, LazyLock, Mutex, Once};
+use std::time::{Duration, Instant};
 use tokio::runtime::{Builder, Runtime};
+use zbus::blocking::fdo::DBusProxy;
 use zbus::blocking::{connection::Connection, proxy::Proxy};
 use zvariant::Value;
 
-const APP_NAME: &'static str = "sun";
+pub const APP_NAME: &'static str = "sun";
 const BUS_NAME: &'static str = "org.freedesktop.Notifications";
 const OBJ_PATH: &'static str = "/org/freedesktop/Notifications";
 const IFACE: &'static str = "org.freedesktop.Notifications";
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
 
 static ZBUS: LazyLock<Connection> = LazyLock::new(|| Connection::session().unwrap());
 static RT: LazyLock<Runtime> = LazyLock::new(|| Builder::new_multi_thread().build().unwrap());
+static LAST_CRITICAL: Mutex<Option<Instant>> = Mutex::new(None);
+static SNOOZE_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+
+// queried on first notification and again after notification server changes
+static SERVER: Mutex<Option<Arc<Server>>> = Mutex::new(None);
+static SERVER_WATCH: Once = Once::new();
+
+struct Server {
+    info: Option<ServerInfo>,
+    // empty if server didn't answer, in that case everything is assumed to be supported
+    capabilities: Vec<String>,
+}
+
+impl Server {
+    fn query() -> Self {
+        let info = ZBUS
+            .call_method(
+                Some(BUS_NAME),
+                OBJ_PATH,
+                Some(IFACE),
+                "GetServerInformation",
+                &(),
+            )
+            .and_then(|msg| msg.body().deserialize::<ServerInfo>())
+            .ok();
+        let capabilities = ZBUS
+            .call_method(
+                Some(BUS_NAME),
+                OBJ_PATH,
+                Some(IFACE),
+                "GetCapabilities",
+                &(),
+            )
+            .and_then(|msg| msg.body().deserialize::<Vec<String>>())
+            .unwrap_or_default();
+
+        Self { info, capabilities }
+    }
+}
+
+// server started after us (e.g. on autostart) or was replaced by another one
+fn watch_server() {
+    RT.spawn_blocking(|| {
+        let changes = DBusProxy::new(&ZBUS)
+            .and_then(|proxy| proxy.receive_name_owner_changed_with_args(&[(0, BUS_NAME)]));
+        let changes = match changes {
+            Ok(changes) => changes,
+            Err(err) => {
+                warn!("cannot watch notification server: {err}");
+                return;
+            }
+        };
+
+        for _ in changes {
+            debug!("notification server changed, querying it again");
+            *SERVER.lock().unwrap() = None;
+        }
+    });
+}
+
+fn server() -> Arc<Server> {
+    SERVER_WATCH.call_once(watch_server);
+
+    let mut cached = SERVER.lock().unwrap();
+
+    if let Some(ref server) = *cached {
+        return Arc::clone(server);
+    }
+
+    let server = Arc::new(Server::query());
+
+    // no answer, nothing worth keeping until the server shows up
+    if server.info.is_some() {
+        *cached = Some(Arc::clone(&server));
+    }
+
+    server
+}
+
+// there is no standard capability for value hint, so known daemons rendering it are listed too
+const VALUE_HINT_CAPABILITIES: [&'static str; 4] = [
+    "body-hints",
+    "x-dunst-stack-tag",
+    "x-kde-display-appname",
+    "x-canonical-private-synchronous",
+];
+
+// icon_path is a base directory for icon, absolute path, file:// uri and icon name
+// (e.g. "battery-low", resolved by server from icon theme) are used as is
+pub fn join_icon(icon_path: &str, icon: &str) -> String {
+    let is_name = !icon.contains('/') && !icon.contains('.');
+
+    if icon_path.is_empty() || icon.starts_with('/') || icon.starts_with("file://") || is_name {
+        return icon.into();
+    }
+
+    format!("{}/{}", icon_path.trim_end_matches('/'), icon)
+}
+
+// module's own app-name wins over global one
+pub fn app_name(module_app_name: &Option<String>) -> String {
+    module_app_name
+        .clone()
+        .unwrap_or_else(|| Config::get().app_name.clone())
+}
+
+// only files can be checked, icon names are resolved by server
+pub fn icon_missing(icon: &str) -> bool {
+    let path = icon.strip_prefix("file://").unwrap_or(icon);
+
+    path.starts_with('/') && !std::path::Path::new(path).exists()
+}
+
+// suffix goes before extension, icon file without suffixed variant is kept as is
+fn icon_with_suffix(icon: &str, suffix: &str) -> String {
+    if icon.is_empty() {
+        return icon.into();
+    }
+
+    let (dir, name) = icon.rsplit_once('/').unwrap_or(("", icon));
+    let name = match name.rsplit_once('.') {
+        Some((stem, ext)) => format!("{stem}{suffix}.{ext}"),
+        None => format!("{name}{suffix}"),
+    };
+    let suffixed = if dir.is_empty() && !icon.starts_with('/') {
+        name
+    } else {
+        format!("{dir}/{name}")
+    };
+    if icon_missing(&suffixed) {
+        return icon.into();
+    }
+
+    suffixed
+}
+
+fn has_capability(names: &[&str]) -> bool {
+    let capabilities = &server().capabilities;
+
+    capabilities.is_empty() || capabilities.iter().any(|cap| names.contains(&cap.as_str()))
+}
+
+// device descriptions and layout names are plain text, but may contain e.g. "&"
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn strip_markup(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_tag = false;
+
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => stripped.push(c),
+            _ => (),
+        }
+    }
+
+    stripped
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[derive(serde::Deserialize, zvariant::Type, Debug)]
+struct ServerInfo {
+    name: String,
+    vendor: String,
+    _version: String,
+    _spec_version: String,
+}
+
+impl ServerInfo {
+    fn is_plasma(&self) -> bool {
+        self.name == "Plasma" || self.vendor == "KDE"
+    }
+
+    fn is_dunst(&self) -> bool {
+        self.name == "dunst"
+    }
+}
+
+static LOW_BATTERIES: LazyLock<Mutex<LowBatteries>> = LazyLock::new(|| {
+    Mutex::new(LowBatteries {
+        entries: Vec::new(),
+        notif: Notification::new(),
+    })
+});
+
+// low batteries of all modules shown as single popup, one entry per module
+struct LowBatteries {
+    entries: Vec<(Module, String, u8)>,
+    notif: Notification,
+}
+
+// modules report low battery here (None when it's not low anymore), false means
+// aggregation is off and module should show its own popup
+//
+// popup is updated whenever a battery becomes low, its capacity changes or it recovers,
+// and closed once there are no low batteries left
+pub fn report_low_battery(module: Module, low: Option<(&str, u8)>) -> bool {
+    let config = Config::get();
+
+    if !config.aggregate_low_battery {
+        return false;
+    }
+
+    let mut low_batteries = LOW_BATTERIES.lock().unwrap();
+    let entry = low.map(|(label, capacity)| (module, label.to_owned(), capacity));
+    let idx = low_batteries
+        .entries
+        .iter()
+        .position(|(other, _, _)| *other == module);
+
+    match (idx, entry) {
+        (Some(idx), Some(entry)) if low_batteries.entries[idx] == entry => return true,
+        (Some(idx), Some(entry)) => low_batteries.entries[idx] = entry,
+        (Some(idx), None) => _ = low_batteries.entries.remove(idx),
+        (None, Some(entry)) => low_batteries.entries.push(entry),
+        (None, None) => return true,
+    }
+
+    if low_batteries.entries.is_empty() {
+        low_batteries.notif.close();
+        return true;
+    }
+
+    let body = low_batteries
+        .entries
+        .iter()
+        .map(|(_, label, capacity)| format!("{label} {capacity}%"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    low_batteries
+        .notif
+        .summary("Low battery")
+        .body(&body)
+        .urgency(Urgency::Critical)
+        .icon_in(&config.battery.icon_path, &config.battery.low_icon)
+        .show();
+
+    true
+}
+
+// everything shown during this run, closed on shutdown so nothing stale is left on screen
+static SHOWN_IDS: LazyLock<Mutex<HashSet<u32>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+// latest id of every replace tag
+static TAG_IDS: LazyLock<Mutex<HashMap<String, u32>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn close_all() {
+    for id in SHOWN_IDS.lock().unwrap().drain() {
+        _ = ZBUS.call_method(
+            Some(BUS_NAME),
+            OBJ_PATH,
+            Some(IFACE),
+            "CloseNotification",
+            &(id),
+        );
+    }
+}
+
+static SNOOZED: LazyLock<Mutex<HashMap<Module, Notification>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// zero duration lifts snooze
+// Err when duration doesn't fit in Instant, e.g. u64::MAX seconds from a bus client
+pub fn snooze(duration: Duration) -> Result<(), String> {
+    let until = Instant::now()
+        .checked_add(duration)
+        .ok_or("snooze duration is too long")?;
+
+    *SNOOZE_UNTIL.lock().unwrap() = Some(until);
+
+    // snooze may be extended meanwhile, then it's up to the later call to restore
+    std::thread::spawn(move || {
+        std::thread::sleep(duration);
+
+        if *SNOOZE_UNTIL.lock().unwrap() == Some(until) {
+            restore_snoozed();
+        }
+    });
+
+    Ok(())
+}
+
+// latest dropped notification of each module is shown once snooze is over
+fn restore_snoozed() {
+    let snoozed = std::mem::take(&mut *SNOOZED.lock().unwrap());
+
+    for (_, mut notif) in snoozed {
+        notif.show();
+    }
+}
+
+fn snoozed() -> bool {
+    SNOOZE_UNTIL
+        .lock()
+        .unwrap()
+        .is_some_and(|until| Instant::now() < until)
+}
 
 pub trait CloseHandler: FnMut(CloseReason) + Sync + Send + 'static {}
 
 impl<T: FnMut(CloseReason) + Sync + Send + 'static> CloseHandler for T {}
 
+pub trait ActionHandler: FnMut(&str) + Sync + Send + 'static {}
+
+impl<T: FnMut(&str) + Sync + Send + 'static> ActionHandler for T {}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum CloseReason {
     Expired,
@@ -40,7 +359,24 @@ pub enum Urgency {
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum Hint {
     Urgency(Urgency),
-    Value(i32),
+    // percent, sent as "value" hint, always clamped to 0..=100 as servers expect
+    Progress(i32),
+    // not kept in server history
+    Transient(bool),
+    // server doesn't play its sound
+    SuppressSound(bool),
+}
+
+// raw image for "image-data" hint, sent as (iiibiiay) struct
+#[derive(Clone, Debug)]
+pub struct ImageData {
+    pub width: i32,
+    pub height: i32,
+    pub rowstride: i32,
+    pub has_alpha: bool,
+    pub bits_per_sample: i32,
+    pub channels: i32,
+    pub data: Vec<u8>,
 }
 
 struct CloseHandlerContext {
@@ -48,14 +384,26 @@ struct CloseHandlerContext {
     close_handler: Option<Box<dyn CloseHandler>>,
 }
 
+struct ActionHandlerContext {
+    notif_id: Arc<AtomicU32>,
+    action_handler: Option<Box<dyn ActionHandler>>,
+}
+
 pub struct Notification {
     id: u32,
+    pub app_name: String,
     pub summary: String,
     pub body: String,
     pub icon: String,
-    pub timeout: i32,
+    // None until set, then urgency decides, see effective_timeout
+    pub timeout: Option<i32>,
     pub hints: HashMap<String, Hint>,
+    pub module: Option<Module>,
+    pub actions: Vec<(String, String)>,
+    tag: Option<String>,
+    image_data: Option<ImageData>,
     close_handler_context: Option<CloseHandlerContext>,
+    action_handler_context: Option<ActionHandlerContext>,
 }
 
 impl From<u32> for CloseReason {
@@ -69,6 +417,16 @@ impl From<u32> for CloseReason {
     }
 }
 
+impl Timeout {
+    // criticals shouldn't auto-dismiss, None leaves it to server
+    pub fn from_urgency(urgency: Urgency) -> Option<Timeout> {
+        match urgency {
+            Urgency::Critical => Some(Timeout::Never),
+            Urgency::Normal => None,
+        }
+    }
+}
+
 impl From<i32> for Timeout {
     fn from(value: i32) -> Self {
         if value > 0 {
@@ -79,26 +437,57 @@ impl From<i32> for Timeout {
     }
 }
 
+impl From<config::Urgency> for Urgency {
+    fn from(value: config::Urgency) -> Self {
+        match value {
+            config::Urgency::Normal => Urgency::Normal,
+            config::Urgency::Critical => Urgency::Critical,
+        }
+    }
+}
+
 impl From<Hint> for Value<'_> {
     fn from(value: Hint) -> Self {
         match value {
             Hint::Urgency(Urgency::Normal) => 1.into(),
             Hint::Urgency(Urgency::Critical) => 2.into(),
-            Hint::Value(value) => value.into(),
+            Hint::Progress(value) => value.into(),
+            Hint::Transient(value) | Hint::SuppressSound(value) => value.into(),
         }
     }
 }
 
+impl From<&ImageData> for Value<'_> {
+    fn from(image: &ImageData) -> Self {
+        (
+            image.width,
+            image.height,
+            image.rowstride,
+            image.has_alpha,
+            image.bits_per_sample,
+            image.channels,
+            image.data.clone(),
+        )
+            .into()
+    }
+}
+
 impl std::default::Default for Notification {
     fn default() -> Self {
         Self {
             id: 0,
+            app_name: String::new(),
             summary: "".into(),
             body: "".into(),
             icon: "".into(),
-            timeout: -1, // server decide
+            timeout: None,
             hints: HashMap::new(),
+            module: None,
+            actions: Vec::new(),
+            tag: None,
+            image_data: None,
             close_handler_context: None,
+            action_handler_context: None,
         }
     }
 }
@@ -108,6 +497,11 @@ impl Notification {
         Self::default()
     }
 
+    pub fn app_name(&mut self, app_name: &str) -> &mut Self {
+        self.app_name = app_name.into();
+        self
+    }
+
     pub fn summary(&mut self, summary: &str) -> &mut Self {
         self.summary = summary.into();
         self
@@ -118,33 +512,88 @@ impl Notification {
         self
     }
 
+    // notifications with the same tag replace each other, even if struct was recreated
+    pub fn replace_tag(&mut self, tag: &str) -> &mut Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
     pub fn icon(&mut self, icon: &str) -> &mut Self {
         self.icon = icon.into();
         self
     }
 
+    pub fn icon_in(&mut self, icon_path: &str, icon: &str) -> &mut Self {
+        self.icon = join_icon(icon_path, icon);
+        self
+    }
+
+    // servers prefer it over icon
+    pub fn image_data(&mut self, image: ImageData) -> &mut Self {
+        self.image_data = Some(image);
+        self
+    }
+
     pub fn urgency(&mut self, urgency: Urgency) -> &mut Self {
         self.hint(Hint::Urgency(urgency));
         self
     }
 
+    pub fn transient(&mut self, transient: bool) -> &mut Self {
+        self.hint(Hint::Transient(transient));
+        self
+    }
+
+    pub fn suppress_sound(&mut self, suppress_sound: bool) -> &mut Self {
+        self.hint(Hint::SuppressSound(suppress_sound));
+        self
+    }
+
     pub fn timeout(&mut self, timeout: Timeout) -> &mut Self {
-        self.timeout = match timeout {
-            Timeout::Millis(millis) => millis as i32,
+        self.timeout = Some(match timeout {
+            Timeout::Millis(millis) => i32::try_from(millis).unwrap_or(i32::MAX),
             Timeout::Never => 0,
-        };
+        });
         self
     }
 
+    // timeout set explicitly wins, otherwise it follows urgency
+    pub fn effective_timeout(&self) -> i32 {
+        if let Some(timeout) = self.timeout {
+            return timeout;
+        }
+
+        let urgency = match self.hints.get("urgency") {
+            Some(Hint::Urgency(urgency)) => *urgency,
+            _ => Urgency::Normal,
+        };
+
+        match Timeout::from_urgency(urgency) {
+            Some(Timeout::Millis(millis)) => i32::try_from(millis).unwrap_or(i32::MAX),
+            Some(Timeout::Never) => 0,
+            // server decides
+            None => -1,
+        }
+    }
+
     pub fn hint(&mut self, hint: Hint) -> &mut Self {
         match hint {
             Hint::Urgency(_) => self.hints.insert("urgency".into(), hint),
-            Hint::Value(_) => self.hints.insert("value".into(), hint),
+            Hint::Progress(value) => self
+                .hints
+                .insert("value".into(), Hint::Progress(value.clamp(0, 100))),
+            Hint::Transient(_) => self.hints.insert("transient".into(), hint),
+            Hint::SuppressSound(_) => self.hints.insert("suppress-sound".into(), hint),
         };
 
         self
     }
 
+    pub fn module(&mut self, module: Module) -> &mut Self {
+        self.module = Some(module);
+        self
+    }
+
     pub fn on_close(&mut self, handler: impl CloseHandler) -> &mut Self {
         if let Some(ref ctx) = self.close_handler_context {
             if let None = ctx.close_handler {
@@ -161,14 +610,247 @@ impl Notification {
         self
     }
 
+    // (key, label) pairs, key is passed to action handler when button is clicked
+    pub fn actions(&mut self, actions: Vec<(String, String)>) -> &mut Self {
+        self.actions = actions;
+        self
+    }
+
+    pub fn on_action(&mut self, handler: impl ActionHandler) -> &mut Self {
+        if let Some(ref ctx) = self.action_handler_context {
+            if ctx.action_handler.is_none() {
+                return self;
+            }
+        }
+
+        let ctx = ActionHandlerContext {
+            notif_id: Arc::new(AtomicU32::new(0)),
+            action_handler: Some(Box::new(handler)),
+        };
+
+        self.action_handler_context = Some(ctx);
+        self
+    }
+
+    pub fn close(&mut self) {
+        if self.id == 0 {
+            return;
+        }
+
+        if let Err(err) = ZBUS.call_method(
+            Some(BUS_NAME),
+            OBJ_PATH,
+            Some(IFACE),
+            "CloseNotification",
+            &(self.id),
+        ) {
+            warn!("close notification call error: {err}");
+        }
+
+        SHOWN_IDS.lock().unwrap().remove(&self.id);
+        self.id = 0;
+    }
+
+    // everything needed to show it later, handlers stay with the original
+    fn snapshot(&self) -> Notification {
+        Notification {
+            id: self.id,
+            app_name: self.app_name.clone(),
+            summary: self.summary.clone(),
+            body: self.body.clone(),
+            icon: self.icon.clone(),
+            timeout: self.timeout,
+            hints: self.hints.clone(),
+            module: self.module,
+            tag: self.tag.clone(),
+            image_data: self.image_data.clone(),
+            actions: self.actions.clone(),
+            close_handler_context: None,
+            action_handler_context: None,
+        }
+    }
+
+    // missing icon file is replaced by module's fallback, then global one, then error icon
+    fn resolve_icon(&self, icon: String, config: &Config) -> String {
+        if !icon_missing(&icon) {
+            return icon;
+        }
+
+        let module_fallback = self.module.and_then(|module| {
+            let (icon_path, fallback) = match module {
+                Module::Sound => (&config.sound.icon_path, &config.sound.fallback_icon),
+                Module::Battery => (&config.battery.icon_path, &config.battery.fallback_icon),
+                Module::Brightness => (
+                    &config.brightness.icon_path,
+                    &config.brightness.fallback_icon,
+                ),
+                Module::Keyboard => (&config.keyboard.icon_path, &config.keyboard.fallback_icon),
+                Module::Thermal => (&config.thermal.icon_path, &config.thermal.fallback_icon),
+                Module::Network => (&config.network.icon_path, &config.network.fallback_icon),
+                Module::Lid => (&config.lid.icon_path, &config.lid.fallback_icon),
+                Module::LockKeys => (&config.lock_keys.icon_path, &config.lock_keys.fallback_icon),
+            };
+
+            fallback
+                .as_deref()
+                .map(|fallback| join_icon(icon_path, fallback))
+        });
+
+        module_fallback
+            .into_iter()
+            .chain(config.fallback_icon.clone())
+            .chain([config.error_icon.clone()])
+            .find(|fallback| !icon_missing(fallback))
+            .unwrap_or(icon)
+    }
+
+    fn log_to_journal(&self) {
+        let priority = match self.hints.get("urgency") {
+            Some(Hint::Urgency(Urgency::Critical)) => journal::PRIORITY_CRIT,
+            _ => journal::PRIORITY_INFO,
+        };
+        let module = self
+            .module
+            .map(|module| format!("{module:?}").to_lowercase())
+            .unwrap_or(APP_NAME.into());
+
+        // journald is not always there, nothing to do about it
+        let _ = journal::send(&[
+            ("MESSAGE", &format!("{}: {}", self.summary, self.body)),
+            ("PRIORITY", &priority.to_string()),
+            ("SYSLOG_IDENTIFIER", APP_NAME),
+            ("SUN_MODULE", &module),
+            ("SUN_SUMMARY", &self.summary),
+            ("SUN_BODY", &self.body),
+        ]);
+    }
+
+    // Plasma shows origin under app name and groups history by it
+    fn add_kde_hints(&self, hints: &mut HashMap<String, Value<'_>>) {
+        let config = Config::get();
+
+        if !config.kde_hints || !server().info.as_ref().is_some_and(|info| info.is_plasma()) {
+            return;
+        }
+
+        let origin = config
+            .kde_origin_name
+            .clone()
+            .or(self.module.map(|module| format!("{module:?}")));
+
+        if let Some(origin) = origin {
+            hints.insert("x-kde-origin-name".into(), origin.into());
+        }
+
+        // same value as standard urgency hint
+        if let Some(urgency) = self.hints.get("urgency") {
+            hints.insert("x-kde-urgency".into(), (*urgency).into());
+        }
+    }
+
+    // shared between all modules, so criticals from different modules
+    // (e.g. laptop and headset battery) are queued instead of stacked
+    fn wait_critical_interval(&self) {
+        if self.hints.get("urgency") != Some(&Hint::Urgency(Urgency::Critical)) {
+            return;
+        }
+
+        let interval = Duration::from_millis(Config::get().critical_interval);
+        let now = Instant::now();
+        // slot is reserved under the lock, waiting for it happens without holding it
+        let slot = {
+            let mut last_critical = LAST_CRITICAL.lock().unwrap();
+            let slot = last_critical.map_or(now, |last| std::cmp::max(now, last + interval));
+
+            *last_critical = Some(slot);
+            slot
+        };
+
+        std::thread::sleep(slot.saturating_duration_since(now));
+    }
+
     pub fn show(&mut self) {
-        static ACTIONS: Vec<String> = Vec::new();
+        let critical = self.hints.get("urgency") == Some(&Hint::Urgency(Urgency::Critical));
+
+        // criticals (e.g. low battery) are too important to be snoozed
+        if !critical && snoozed() {
+            if let Some(module) = self.module.filter(|_| Config::get().restore_after_snooze) {
+                SNOOZED.lock().unwrap().insert(module, self.snapshot());
+            }
 
-        let hints = self
+            return;
+        }
+
+        self.wait_critical_interval();
+
+        let actions = self
+            .actions
+            .iter()
+            .flat_map(|(key, label)| [key, label])
+            .collect::<Vec<_>>();
+        let mut hints = self
             .hints
             .iter()
-            .map(|(name, hint)| (name, (*hint).into()))
+            .map(|(name, hint)| (name.clone(), (*hint).into()))
             .collect::<HashMap<_, Value<'_>>>();
+
+        self.add_kde_hints(&mut hints);
+
+        if let Some(ref image) = self.image_data {
+            hints.insert("image-data".into(), image.into());
+        }
+
+        let config = Config::get();
+        let format = match self.hints.get("urgency") {
+            Some(Hint::Urgency(Urgency::Critical)) => &config.urgency_format.critical,
+            _ => &config.urgency_format.normal,
+        };
+        let icon = match format.icon_suffix {
+            Some(ref suffix) => icon_with_suffix(&self.icon, suffix),
+            None => self.icon.clone(),
+        };
+        let icon = self.resolve_icon(icon, &config);
+
+        for hint in format.hints.iter() {
+            hints.insert(hint.name.clone(), hint.value.clone().into());
+        }
+
+        if let Some(ref tag) = self.tag {
+            if let Some(id) = TAG_IDS.lock().unwrap().get(tag) {
+                self.id = *id;
+            }
+
+            // dunst also replaces by it notifications of other apps, e.g. dunstify -h
+            if server().info.as_ref().is_some_and(|info| info.is_dunst()) {
+                hints.insert("x-dunst-stack-tag".into(), tag.clone().into());
+            }
+        }
+
+        if !has_capability(&VALUE_HINT_CAPABILITIES) {
+            hints.remove("value");
+        }
+
+        // notify-osd draws value as a gauge only in synchronous bubbles
+        if hints.contains_key("value") && has_capability(&["x-canonical-private-synchronous"]) {
+            let name = self.tag.clone().unwrap_or_else(|| config.app_name.clone());
+
+            hints.insert("x-canonical-private-synchronous".into(), name.into());
+        }
+
+        let body = match (
+            Config::get().format.markup,
+            has_capability(&["body-markup"]),
+        ) {
+            (true, true) | (false, false) => self.body.clone(),
+            (true, false) => strip_markup(&self.body),
+            (false, true) => escape_markup(&self.body),
+        };
+
+        // modules set their own, others (e.g. error popups) go under global one
+        let app_name = match self.app_name.as_str() {
+            "" => config.app_name.clone(),
+            app_name => app_name.to_owned(),
+        };
         let notif_id = ZBUS
             .call_method(
                 Some(BUS_NAME),
@@ -176,38 +858,64 @@ impl Notification {
                 Some(IFACE),
                 "Notify",
                 &(
-                    APP_NAME,
+                    &app_name,
                     self.id,
-                    &self.icon,
+                    &icon,
                     &self.summary,
-                    &self.body,
-                    &ACTIONS,
+                    &body,
+                    &actions,
                     hints,
-                    self.timeout,
+                    self.effective_timeout(),
                 ),
             )
-            .unwrap()
-            .body()
-            .deserialize::<u32>()
-            .unwrap();
+            .and_then(|msg| msg.body().deserialize::<u32>());
+
+        // close handler stays unarmed until server gives us an id it will report back
+        let notif_id = match notif_id {
+            Ok(0) => {
+                warn!("notification server returned invalid id 0");
+                return;
+            }
+            Ok(notif_id) => notif_id,
+            Err(err) => {
+                warn!("notify call error: {err}");
+                return;
+            }
+        };
 
         self.id = notif_id;
+        SHOWN_IDS.lock().unwrap().insert(notif_id);
+
+        if let Some(ref tag) = self.tag {
+            TAG_IDS.lock().unwrap().insert(tag.clone(), notif_id);
+        }
+        debug!(
+            "notification {notif_id} shown: {} - {}",
+            self.summary, self.body
+        );
+
+        if Config::get().journal_notifications {
+            self.log_to_journal();
+        }
 
         if let Some(ref mut ctx) = self.close_handler_context {
             if let Some(mut handler) = ctx.close_handler.take() {
-                let notif_id = Arc::clone(&ctx.notif_id);
-                let proxy = Proxy::new(&ZBUS, BUS_NAME, OBJ_PATH, IFACE).unwrap();
-
-                RT.spawn(async move {
-                    for msg in proxy.receive_signal("NotificationClosed").unwrap() {
-                        let body = msg.body();
-                        let structure = body.deserialize::<zvariant::Structure>().unwrap();
-                        let fields = structure.fields();
-
-                        if matches!(fields[0], Value::U32(id) if id == notif_id.load(Ordering::Relaxed)) {
-                            handler(CloseReason::from(fields[1].downcast_ref::<u32>().unwrap()));
-                        }
-                    }
+                subscribe(
+                    "NotificationClosed",
+                    Arc::clone(&ctx.notif_id),
+                    move |reason| {
+                        handler(CloseReason::from(reason.downcast_ref::<u32>().unwrap()));
+                    },
+                );
+            }
+
+            ctx.notif_id.store(notif_id, Ordering::Relaxed);
+        }
+
+        if let Some(ref mut ctx) = self.action_handler_context {
+            if let Some(mut handler) = ctx.action_handler.take() {
+                subscribe("ActionInvoked", Arc::clone(&ctx.notif_id), move |key| {
+                    handler(key.downcast_ref::<&str>().unwrap());
                 });
             }
 
@@ -215,3 +923,101 @@ impl Notification {
         }
     }
 }
+
+// calls handler with second signal argument for signals related to notif_id
+fn subscribe(
+    signal: &'static str,
+    notif_id: Arc<AtomicU32>,
+    mut handler: impl FnMut(&Value) + Send + 'static,
+) {
+    // iterating blocking signal stream and sleeping would stall async workers
+    RT.spawn_blocking(move || {
+        // signal stream ends when notification daemon restarts, so subscribe again
+        loop {
+            let signals = Proxy::new(&ZBUS, BUS_NAME, OBJ_PATH, IFACE)
+                .and_then(|proxy| proxy.receive_signal(signal));
+
+            for msg in signals.into_iter().flatten() {
+                let body = msg.body();
+                let structure = body.deserialize::<zvariant::Structure>().unwrap();
+                let fields = structure.fields();
+
+                if matches!(fields[0], Value::U32(id) if id == notif_id.load(Ordering::Relaxed)) {
+                    handler(&fields[1]);
+                }
+            }
+
+            std::thread::sleep(RESUBSCRIBE_DELAY);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_icon_edge_cases() {
+        assert_eq!(join_icon("/icons", "low.png"), "/icons/low.png");
+        assert_eq!(join_icon("/icons/", "low.png"), "/icons/low.png");
+        assert_eq!(join_icon("/icons//", "sub/low"), "/icons/sub/low");
+        assert_eq!(join_icon("/icons", "/abs/low.png"), "/abs/low.png");
+        assert_eq!(
+            join_icon("/icons", "file:///abs/low.png"),
+            "file:///abs/low.png"
+        );
+        assert_eq!(join_icon("/icons", "battery-low"), "battery-low");
+        assert_eq!(join_icon("", "low.png"), "low.png");
+        assert_eq!(join_icon("/icons", ""), "");
+    }
+
+    #[test]
+    fn timeout_follows_urgency_only_when_unset() {
+        let mut notif = Notification::new();
+
+        assert_eq!(notif.effective_timeout(), -1);
+
+        notif.urgency(Urgency::Critical);
+        assert_eq!(notif.effective_timeout(), 0);
+
+        notif.timeout(Timeout::Millis(3000));
+        assert_eq!(notif.effective_timeout(), 3000);
+
+        notif.urgency(Urgency::Normal).timeout(Timeout::Never);
+        assert_eq!(notif.effective_timeout(), 0);
+    }
+
+    #[test]
+    fn image_data_round_trip() {
+        let image = ImageData {
+            width: 2,
+            height: 1,
+            rowstride: 8,
+            has_alpha: true,
+            bits_per_sample: 8,
+            channels: 4,
+            data: vec![255, 0, 0, 255, 0, 255, 0, 128],
+        };
+        let value = Value::from(&image);
+
+        assert_eq!(value.value_signature().to_string(), "(iiibiiay)");
+
+        let ctxt = zvariant::serialized::Context::new_dbus(zvariant::LE, 0);
+        let bytes = zvariant::to_bytes(ctxt, &value).unwrap();
+        let (decoded, _) = bytes.deserialize::<Value>().unwrap();
+        let decoded = <(i32, i32, i32, bool, i32, i32, Vec<u8>)>::try_from(decoded).unwrap();
+
+        assert_eq!(
+            decoded,
+            (
+                image.width,
+                image.height,
+                image.rowstride,
+                image.has_alpha,
+                image.bits_per_sample,
+                image.channels,
+                image.data,
+            )
+        );
+    }
+}
@@ -1,9 +1,10 @@
-use crate::config::Config;
-use crate::notif::{CloseReason, Hint, Notification, Timeout, Urgency};
+use crate::config::{Config, Sound};
+use crate::notif::{self, CloseReason, Hint, Notification, Timeout, Urgency};
+use crate::Module;
 use libpulse_binding as pa;
 use pa::callbacks::ListResult;
 use pa::context::introspect::{SinkInfo, SourceInfo};
-use pa::context::subscribe::{Facility, InterestMaskSet};
+use pa::context::subscribe::{Facility, InterestMaskSet, Operation};
 use pa::context::{Context, FlagSet};
 use pa::mainloop::standard::{IterateResult, Mainloop};
 use pa::proplist::Proplist;
@@ -12,10 +13,14 @@ use pa::volume::Volume;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::{Duration, SystemTime};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use zbus::blocking::connection;
 use zvariant;
 
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const AUTO_CHANGE_WINDOW: Duration = Duration::from_millis(500);
+
 // workaround for trait impl on external types error
 macro_rules! pa_info_eq {
     ($info1:ident, $info2:ident) => {
@@ -28,39 +33,59 @@ macro_rules! pa_info_eq {
 #[derive(Debug, Clone)]
 struct PulseEvent {
     facility: Facility,
+    operation: Option<Operation>,
+    index: u32,
 }
 
 enum PollResult {
     Data(Vec<PulseEvent>),
     Timeout,
+    Interrupted,
 }
 
 struct ContextHelper {
     main_loop: Mainloop,
     context: Context,
     event_queue: Rc<RefCell<Vec<PulseEvent>>>,
+    deadline: Option<Instant>,
+    // pinned devices in use, replaced on reload only when new ones exist
+    sink_name: Option<String>,
+    source_name: Option<String>,
 }
 
 struct NotifHelper {
     zbus: zbus::blocking::Connection,
     sink_notif: Notification,
     source_notif: Notification,
+    // index of sink last notification was about, see notify-device-change
+    last_sink: Option<u32>,
+    // last low battery warnings, see bluetooth-low-battery-suppress-window
+    sink_low_shown_at: Option<Instant>,
+    source_low_shown_at: Option<Instant>,
 }
 
 impl ContextHelper {
-    fn new() -> Self {
-        let mut main_loop = Mainloop::new().unwrap();
-        let mut context = Context::new(&main_loop, "dunst-centre").unwrap();
+    // with NOFAIL it waits for server to appear (e.g. pipewire-pulse starting after us),
+    // anything else, like missing pulse library or server dying meanwhile, is an error
+    fn new() -> Result<Self, String> {
+        let mut main_loop = Mainloop::new().ok_or("cannot create PulseAudio mainloop")?;
+        let mut context = Context::new(&main_loop, &Config::get().app_name)
+            .ok_or("cannot create PulseAudio context")?;
 
         context
             .connect(None, FlagSet::NOFAIL | FlagSet::NOAUTOSPAWN, None)
-            .unwrap();
+            .map_err(|err| format!("cannot connect to PulseAudio server: {err}"))?;
 
         loop {
             match main_loop.iterate(true) {
-                IterateResult::Success(_) => {
-                    if context.get_state() == pa::context::State::Ready {
-                        context.subscribe(InterestMaskSet::SINK | InterestMaskSet::SOURCE, |res| {
+                IterateResult::Success(_) => match context.get_state() {
+                    pa::context::State::Ready => {
+                        let mask = InterestMaskSet::SINK
+                            | InterestMaskSet::SOURCE
+                            | InterestMaskSet::SINK_INPUT
+                            | InterestMaskSet::SERVER;
+
+                        context.subscribe(mask, |res| {
                             if !res {
                                 panic!("failed to subscribe on PulseAudio events")
                             }
@@ -68,16 +93,23 @@ impl ContextHelper {
 
                         break;
                     }
-                }
-                _ => panic!("cannot initialize PulseAudio context"),
+                    pa::context::State::Failed | pa::context::State::Terminated => {
+                        return Err(format!("PulseAudio connection failed: {}", context.errno()));
+                    }
+                    _ => (),
+                },
+                _ => return Err("cannot initialize PulseAudio context".into()),
             }
         }
 
-        Self {
+        Ok(Self {
             main_loop,
             context,
             event_queue: Rc::new(RefCell::new(Vec::new())),
-        }
+            deadline: None,
+            sink_name: Config::get().sound.sink_name.clone(),
+            source_name: Config::get().sound.source_name.clone(),
+        })
     }
 
     fn subscribe(&mut self) {
@@ -85,10 +117,12 @@ impl ContextHelper {
 
         self.context
             .set_subscribe_callback(Some(Box::new(
-                move |facility, _operation, _index| match facility.unwrap() {
-                    Facility::Sink | Facility::Source => {
+                move |facility, operation, index| match facility.unwrap() {
+                    Facility::Sink | Facility::Source | Facility::SinkInput | Facility::Server => {
                         let event = PulseEvent {
                             facility: facility.unwrap(),
+                            operation,
+                            index,
                         };
 
                         event_queue.borrow_mut().push(event);
@@ -98,7 +132,15 @@ impl ContextHelper {
             )));
     }
 
+    // returns Interrupted at least every MAX_POLL_INTERVAL, so routine can notice
+    // shutdown/reload even if SIGUSR1 was delivered outside of poll,
+    // original timeout is kept across such wakeups
     fn poll_events(&mut self, timeout: Option<MicroSeconds>) -> PollResult {
+        if self.deadline.is_none() {
+            self.deadline =
+                timeout.map(|timeout| Instant::now() + Duration::from_micros(timeout.0));
+        }
+
         loop {
             let mut event_queue = self.event_queue.borrow_mut();
 
@@ -106,72 +148,135 @@ impl ContextHelper {
                 let event_queue_copy = event_queue.clone();
 
                 event_queue.clear();
+                self.deadline = None;
 
                 return PollResult::Data(event_queue_copy);
             }
 
             drop(event_queue);
 
-            self.main_loop.prepare(timeout).unwrap();
+            let wait = self.deadline.map_or(MAX_POLL_INTERVAL, |deadline| {
+                std::cmp::min(
+                    deadline.saturating_duration_since(Instant::now()),
+                    MAX_POLL_INTERVAL,
+                )
+            });
+
+            self.main_loop
+                .prepare(Some(MicroSeconds(wait.as_micros() as u64)))
+                .unwrap();
 
             let poll_ret = self.main_loop.poll().unwrap();
             let dispatched = self.main_loop.dispatch().unwrap();
 
-            if timeout.is_some() && poll_ret == 0 && dispatched == 0 {
-                return PollResult::Timeout;
+            // mainloop swallows EINTR and reports it as 0 ready fds, same as timeout
+            if poll_ret == 0 && dispatched == 0 {
+                if self
+                    .deadline
+                    .is_some_and(|deadline| deadline <= Instant::now())
+                {
+                    self.deadline = None;
+                    return PollResult::Timeout;
+                }
+
+                return PollResult::Interrupted;
+            }
+        }
+    }
+
+    // pinned sink takes precedence over the default one,
+    // unknown or unplugged pinned sink falls back to the default one
+    fn get_default_sink_info(&mut self) -> Option<SinkInfo<'static>> {
+        if let Some(name) = self.sink_name.clone() {
+            match self.sink_info(&name) {
+                Some(info) => return Some(info),
+                None => warn!("sink {name} not found, using default sink"),
+            }
+        }
+
+        self.sink_info("@DEFAULT_SINK@")
+    }
+
+    // pinned source takes precedence over the default one,
+    // unknown or unplugged pinned source falls back to the default one
+    fn get_default_source_info(&mut self) -> Option<SourceInfo<'static>> {
+        if let Some(name) = self.source_name.clone() {
+            match self.source_info(&name) {
+                Some(info) => return Some(info),
+                None => warn!("source {name} not found, using default source"),
             }
         }
+
+        self.source_info("@DEFAULT_SOURCE@")
+    }
+
+    // None keeps the previous pin, so a typo saved in config doesn't switch devices
+    fn repin_sink(&mut self, name: Option<String>) -> Option<SinkInfo<'static>> {
+        let info = self.sink_info(name.as_deref().unwrap_or("@DEFAULT_SINK@"))?;
+
+        self.sink_name = name;
+        Some(info)
     }
 
-    fn get_default_sink_info(&mut self) -> SinkInfo<'static> {
+    fn repin_source(&mut self, name: Option<String>) -> Option<SourceInfo<'static>> {
+        let info = self.source_info(name.as_deref().unwrap_or("@DEFAULT_SOURCE@"))?;
+
+        self.source_name = name;
+        Some(info)
+    }
+
+    fn sink_info(&mut self, name: &str) -> Option<SinkInfo<'static>> {
         let container = Rc::new(RefCell::new(None));
         let container_clone = Rc::clone(&container);
 
         self.context
             .introspect()
-            .get_sink_info_by_name("@DEFAULT_SINK@", move |res| match res {
-                ListResult::Item(info) => {
-                    *container_clone.borrow_mut() = Some(info.to_owned());
+            .get_sink_info_by_name(name, move |res| {
+                let mut container = container_clone.borrow_mut();
+
+                match res {
+                    ListResult::Item(info) => *container = Some(Some(info.to_owned())),
+                    ListResult::End if container.is_none() => *container = Some(None),
+                    ListResult::End => (),
+                    // e.g. no such sink
+                    ListResult::Error => *container = Some(None),
                 }
-                ListResult::End => (),
-                ListResult::Error => panic!("error iterate result"),
             });
 
-        loop {
-            match self.main_loop.iterate(true) {
-                IterateResult::Success(_) => {
-                    if container.borrow().is_some() {
-                        return Rc::into_inner(container).unwrap().into_inner().unwrap();
-                    }
-                }
-                _ => panic!("get default sink info error"),
-            }
-        }
+        self.wait_for(&container)
     }
 
-    fn get_default_source_info(&mut self) -> SourceInfo<'static> {
+    fn source_info(&mut self, name: &str) -> Option<SourceInfo<'static>> {
         let container = Rc::new(RefCell::new(None));
         let container_clone = Rc::clone(&container);
 
-        self.context.introspect().get_source_info_by_name(
-            "@DEFAULT_SOURCE@",
-            move |res| match res {
-                ListResult::Item(info) => {
-                    *container_clone.borrow_mut() = Some(info.to_owned());
+        self.context
+            .introspect()
+            .get_source_info_by_name(name, move |res| {
+                let mut container = container_clone.borrow_mut();
+
+                match res {
+                    ListResult::Item(info) => *container = Some(Some(info.to_owned())),
+                    ListResult::End if container.is_none() => *container = Some(None),
+                    ListResult::End => (),
+                    // e.g. no such source
+                    ListResult::Error => *container = Some(None),
                 }
-                ListResult::End => (),
-                ListResult::Error => panic!("error iterate result"),
-            },
-        );
+            });
 
+        self.wait_for(&container)
+    }
+
+    // outer None while introspection is still running
+    fn wait_for<T>(&mut self, container: &Rc<RefCell<Option<Option<T>>>>) -> Option<T> {
         loop {
             match self.main_loop.iterate(true) {
                 IterateResult::Success(_) => {
-                    if container.borrow().is_some() {
-                        return Rc::into_inner(container).unwrap().into_inner().unwrap();
+                    if let Some(info) = container.borrow_mut().take() {
+                        return info;
                     }
                 }
-                _ => panic!("get default source info error"),
+                _ => panic!("introspect info error"),
             }
         }
     }
@@ -179,10 +284,23 @@ impl ContextHelper {
 
 impl NotifHelper {
     fn new() -> Self {
+        let mut sink_notif = Notification::new();
+        let mut source_notif = Notification::new();
+
+        sink_notif
+            .module(Module::Sound)
+            .replace_tag("sun-sound-sink");
+        source_notif
+            .module(Module::Sound)
+            .replace_tag("sun-sound-source");
+
         Self {
             zbus: connection::Connection::system().unwrap(),
-            sink_notif: Notification::new(),
-            source_notif: Notification::new(),
+            sink_notif,
+            source_notif,
+            last_sink: None,
+            sink_low_shown_at: None,
+            source_low_shown_at: None,
         }
     }
 
@@ -233,13 +351,47 @@ impl NotifHelper {
         let config = Config::get();
         let config_sound = &config.sound;
 
+        if config_sound.sink_off {
+            return poll_timeout;
+        }
+
+        let volume =
+            pa_volume_to_percent(sink_info.volume.avg().0, config_sound.volume_display_step);
+        let description = sink_info.description.as_deref().unwrap_or("unknown device");
+        let mut body = String::from("Volume");
+
+        if let Some(bus) = sink_info.proplist.get_str("device.bus") {
+            if bus == "bluetooth" && config_sound.sink_show_description {
+                body = description.into();
+            }
+        }
+
+        let switched = self
+            .last_sink
+            .replace(sink_info.index)
+            .is_some_and(|index| index != sink_info.index);
+
+        if switched && config_sound.notify_device_change {
+            body = format!("Output switched to {description}");
+        }
+
+        let values = [
+            ("name", "Sound"),
+            ("value", &volume.to_string()),
+            ("status", if sink_info.mute { "muted" } else { "unmuted" }),
+            ("device", description),
+        ];
+
         self.sink_notif
-            .timeout(Timeout::from(config_sound.sink_notification_timeout))
-            .summary("Sound")
-            .body("Volume")
-            .icon(&config_sound.icon_path)
-            .urgency(Urgency::Normal)
-            .hint(Hint::Value(pa_volume_to_percent(sink_info.volume.avg().0)))
+            .app_name(&notif::app_name(&config_sound.app_name))
+            .timeout(
+                config_sound
+                    .format
+                    .timeout(Timeout::from(config_sound.sink_notification_timeout)),
+            )
+            .summary(&config_sound.format.summary("Sound", &values))
+            .body(&config_sound.format.body(&body, &values))
+            .urgency(config_sound.format.urgency())
             .on_close(|reason| {
                 if matches!(reason, CloseReason::ClosedByUser)
                     && LOW_BATTERY.load(Ordering::Relaxed)
@@ -248,138 +400,488 @@ impl NotifHelper {
                 }
             });
 
-        if let Some(bus) = sink_info.proplist.get_str("device.bus") {
-            if bus == "bluetooth" {
-                self.sink_notif.body = sink_info.description.clone().unwrap().to_string();
-            }
-        }
+        set_volume(&mut self.sink_notif, volume, config_sound);
 
         // we can receive new device event before it can register battery in dbus
         if let Some(battery) = self.bluetooth_battery(&sink_info.proplist) {
+            let low = battery <= config_sound.sink_bluetooth_low_battery_warn_at;
+            let label = sink_info.description.as_deref().unwrap_or("Headset");
+            let aggregated =
+                notif::report_low_battery(Module::Sound, low.then_some((label, battery)));
+
             poll_timeout = Some(
                 MicroSeconds::from_secs(config_sound.sink_bluetooth_battery_poll_timeout).unwrap(),
             );
 
-            if battery <= config_sound.sink_bluetooth_low_battery_warn_at {
+            if low && !aggregated {
                 LOW_BATTERY.store(true, Ordering::Relaxed);
                 self.sink_notif.timeout(Timeout::from(
                     config_sound.sink_bluetooth_low_battery_timeout,
                 ));
                 self.sink_notif.urgency(Urgency::Critical);
-                self.sink_notif
-                    .body
-                    .push_str(&format!(" ({battery}%) Low battery"));
             } else {
                 LOW_BATTERY.store(false, Ordering::Relaxed);
-                self.sink_notif.body.push_str(&format!(" ({}%)", battery));
             }
+
+            if config_sound.sink_show_battery {
+                self.sink_notif.body.push_str(&format!(" ({battery}%)"));
+            }
+
+            if low && config_sound.sink_show_low_battery {
+                self.sink_notif.body.push_str(" Low battery");
+            }
+        } else if sink_info.proplist.get_str("device.bus").as_deref() != Some("bluetooth") {
+            // switched to other sink, battery may be just not registered yet on bluetooth one
+            notif::report_low_battery(Module::Sound, None);
+            LOW_BATTERY.store(false, Ordering::Relaxed);
         }
 
         if sink_info.mute {
-            self.sink_notif.summary.push_str(" muted");
-            self.sink_notif.icon += &config_sound.sink_muted_icon;
+            self.sink_notif.summary.push_str(&config_sound.muted_suffix);
+            self.sink_notif
+                .icon_in(&config_sound.icon_path, &config_sound.sink_muted_icon);
         } else if poll_timeout.is_some() {
-            self.sink_notif.icon += &config_sound.sink_bluetooth_icon;
+            self.sink_notif
+                .icon_in(&config_sound.icon_path, &config_sound.sink_bluetooth_icon);
         } else {
-            self.sink_notif.icon += &config_sound.sink_icon;
+            self.sink_notif
+                .icon_in(&config_sound.icon_path, &config_sound.sink_icon);
         }
 
+        let low = LOW_BATTERY.load(Ordering::Relaxed);
+
         if !only_low
-            || (LOW_BATTERY.load(Ordering::Relaxed) && !NOTIF_CLOSED.load(Ordering::Relaxed))
+            || (low
+                && !NOTIF_CLOSED.load(Ordering::Relaxed)
+                && !low_warning_suppressed(self.sink_low_shown_at))
         {
             self.sink_notif.show();
             NOTIF_CLOSED.store(false, Ordering::Relaxed);
+
+            if low {
+                self.sink_low_shown_at = Some(Instant::now());
+            }
         }
 
         poll_timeout
     }
 
-    fn show_source_notification(&mut self, source_info: &SourceInfo<'static>) {
-        let config_sound = Config::get().sound;
+    fn show_source_notification(
+        &mut self,
+        source_info: &SourceInfo<'static>,
+        only_low: bool,
+    ) -> Option<MicroSeconds> {
+        static NOTIF_CLOSED: AtomicBool = AtomicBool::new(false);
+        static LOW_BATTERY: AtomicBool = AtomicBool::new(false);
+
+        let mut poll_timeout = None;
+        let config = Config::get();
+        let config_sound = &config.sound;
+
+        if config_sound.source_off {
+            return poll_timeout;
+        }
+
+        let volume =
+            pa_volume_to_percent(source_info.volume.avg().0, config_sound.volume_display_step);
+        let values = [
+            ("name", "Mic"),
+            ("value", &volume.to_string()),
+            ("status", if source_info.mute { "muted" } else { "unmuted" }),
+            (
+                "device",
+                source_info
+                    .description
+                    .as_deref()
+                    .unwrap_or("unknown device"),
+            ),
+        ];
 
         self.source_notif
-            .summary("Mic")
-            .body("Volume")
-            .urgency(Urgency::Normal)
-            .timeout(Timeout::from(config_sound.source_notification_timeout))
-            .icon(&config_sound.icon_path)
-            .hint(Hint::Value(pa_volume_to_percent(
-                source_info.volume.avg().0,
-            )));
+            .app_name(&notif::app_name(&config_sound.app_name))
+            .summary(&config_sound.format.summary("Mic", &values))
+            .body(&config_sound.format.body("Volume", &values))
+            .urgency(config_sound.format.urgency())
+            .timeout(
+                config_sound
+                    .format
+                    .timeout(Timeout::from(config_sound.source_notification_timeout)),
+            )
+            .on_close(|reason| {
+                if matches!(reason, CloseReason::ClosedByUser)
+                    && LOW_BATTERY.load(Ordering::Relaxed)
+                {
+                    NOTIF_CLOSED.store(true, Ordering::Relaxed);
+                }
+            });
+
+        set_volume(&mut self.source_notif, volume, config_sound);
+
+        if let Some(battery) = self.bluetooth_battery(&source_info.proplist) {
+            // headset is already in aggregated popup via its sink
+            let low = battery <= config_sound.source_bluetooth_low_battery_warn_at
+                && !config.aggregate_low_battery;
+
+            poll_timeout = Some(
+                MicroSeconds::from_secs(config_sound.source_bluetooth_battery_poll_timeout)
+                    .unwrap(),
+            );
+
+            if low {
+                LOW_BATTERY.store(true, Ordering::Relaxed);
+                self.source_notif.timeout(Timeout::from(
+                    config_sound.source_bluetooth_low_battery_timeout,
+                ));
+                self.source_notif.urgency(Urgency::Critical);
+            } else {
+                LOW_BATTERY.store(false, Ordering::Relaxed);
+            }
+
+            if config_sound.source_show_battery {
+                self.source_notif.body.push_str(&format!(" ({battery}%)"));
+            }
+
+            if low && config_sound.source_show_low_battery {
+                self.source_notif.body.push_str(" Low battery");
+            }
+        } else {
+            LOW_BATTERY.store(false, Ordering::Relaxed);
+        }
 
         if source_info.mute {
-            self.source_notif.summary.push_str(" muted");
-            self.source_notif.icon += &config_sound.source_muted_icon;
+            self.source_notif
+                .summary
+                .push_str(&config_sound.muted_suffix);
+            self.source_notif
+                .icon_in(&config_sound.icon_path, &config_sound.source_muted_icon);
         } else {
-            self.source_notif.icon += &config_sound.source_icon;
+            self.source_notif
+                .icon_in(&config_sound.icon_path, &config_sound.source_icon);
         }
 
-        self.source_notif.show();
+        let low = LOW_BATTERY.load(Ordering::Relaxed);
+
+        if !only_low
+            || (low
+                && !NOTIF_CLOSED.load(Ordering::Relaxed)
+                && !low_warning_suppressed(self.source_low_shown_at))
+        {
+            self.source_notif.show();
+            NOTIF_CLOSED.store(false, Ordering::Relaxed);
+
+            if low {
+                self.source_low_shown_at = Some(Instant::now());
+            }
+        }
+
+        poll_timeout
+    }
+}
+
+// battery hovering around warn-at shouldn't keep popping warning up on every poll
+fn low_warning_suppressed(shown_at: Option<Instant>) -> bool {
+    let window = Duration::from_secs(Config::get().sound.bluetooth_low_battery_suppress_window);
+
+    shown_at.is_some_and(|at| at.elapsed() < window)
+}
+
+// sink and source batteries are polled together, as often as either needs it
+fn earliest(a: Option<MicroSeconds>, b: Option<MicroSeconds>) -> Option<MicroSeconds> {
+    a.into_iter().chain(b).min()
+}
+
+// text-only daemons ignore value hint, so percentage goes to the body instead,
+// same for boosted volume as servers expect value in 0..=100
+fn set_volume(notif: &mut Notification, percent: i32, config_sound: &Sound) {
+    notif
+        .transient(config_sound.transient)
+        .suppress_sound(config_sound.suppress_sound);
+
+    if config_sound.value_hint {
+        notif.hint(Hint::Progress(percent));
+
+        if percent > 100 && config_sound.allow_overamplification {
+            notif.body.push_str(&format!(" {percent}%"));
+        }
+    } else {
+        notif.hints.remove("value");
+        notif.body.push_str(&format!(" {percent}%"));
     }
 }
 
-fn pa_volume_to_percent(volume: u32) -> i32 {
-    ((volume * 100 + Volume::NORMAL.0 / 2) / Volume::NORMAL.0) as i32
+// step only snaps displayed value, actual volume stays untouched
+fn pa_volume_to_percent(volume: u32, step: u32) -> i32 {
+    let percent = (volume * 100 + Volume::NORMAL.0 / 2) / Volume::NORMAL.0;
+    let step = std::cmp::max(step, 1);
+
+    ((percent + step / 2) / step * step) as i32
+}
+
+pub fn test_notification() -> Notification {
+    let config = Config::get();
+    let config_sound = &config.sound;
+    let mut notif = Notification::new();
+
+    notif
+        .module(Module::Sound)
+        .app_name(&notif::app_name(&config_sound.app_name))
+        .timeout(
+            config_sound
+                .format
+                .timeout(Timeout::from(config_sound.sink_notification_timeout)),
+        )
+        .summary("Sound")
+        .body("Volume")
+        .urgency(config_sound.format.urgency())
+        .icon_in(&config_sound.icon_path, &config_sound.sink_icon)
+        .hint(Hint::Progress(50));
+
+    notif
+}
+
+pub fn icons() -> Vec<String> {
+    let config = Config::get();
+    let config_sound = &config.sound;
+
+    [
+        &config_sound.sink_icon,
+        &config_sound.sink_muted_icon,
+        &config_sound.sink_bluetooth_icon,
+        &config_sound.source_icon,
+        &config_sound.source_muted_icon,
+    ]
+    .iter()
+    .map(|icon| notif::join_icon(&config_sound.icon_path, icon))
+    .collect()
 }
 
 pub fn routine() -> impl crate::Routine {
     || {
-        let mut context_helper = ContextHelper::new();
+        let mut context_helper = match ContextHelper::new() {
+            Ok(context_helper) => context_helper,
+            Err(err) => {
+                warn!("{err}, sound module is not running");
+                return;
+            }
+        };
+        let (Some(mut default_sink), Some(mut default_source)) = (
+            context_helper.get_default_sink_info(),
+            context_helper.get_default_source_info(),
+        ) else {
+            info!("no sink or source found, sound module is not running");
+            return;
+        };
         let mut notif_helper = NotifHelper::new();
-        let mut default_sink = context_helper.get_default_sink_info();
-        let mut default_source = context_helper.get_default_source_info();
-        let mut poll_timeout = notif_helper
-            .bluetooth_battery(&context_helper.get_default_sink_info().proplist)
+        let mut sink_poll_timeout =
+            notif_helper
+                .bluetooth_battery(&default_sink.proplist)
+                .map(|_| {
+                    MicroSeconds::from_secs(Config::get().sound.sink_bluetooth_battery_poll_timeout)
+                        .unwrap()
+                });
+        let mut source_poll_timeout = notif_helper
+            .bluetooth_battery(&default_source.proplist)
             .map(|_| {
-                MicroSeconds::from_millis(Config::get().sound.sink_bluetooth_battery_poll_timeout)
+                MicroSeconds::from_secs(Config::get().sound.source_bluetooth_battery_poll_timeout)
                     .unwrap()
             });
 
+        // streams appearing and going away, see suppress-auto-changes
+        let mut last_sink_input_change = None::<Instant>;
+        // config reloads only interrupt the routine, see PollResult::Interrupted
+        let mut applied_config = Config::get();
+        // first sink change not shown yet, see volume-debounce-ms
+        let mut pending_sink_since = None::<Instant>;
+
         context_helper.subscribe();
 
         loop {
-            if Config::get().sound.off {
+            if Config::get().sound.off || crate::shutting_down() {
                 context_helper.main_loop.quit(pa::def::Retval(0));
                 context_helper.context.disconnect();
                 break;
             }
 
-            match context_helper.poll_events(poll_timeout) {
+            if crate::take_refresh(Module::Sound) {
+                pending_sink_since = None;
+                sink_poll_timeout = notif_helper.show_sink_notification(&default_sink, false);
+                source_poll_timeout = notif_helper.show_source_notification(&default_source, false);
+            }
+
+            let debounce = Duration::from_millis(Config::get().sound.volume_debounce_ms);
+
+            // latest state is shown once window elapses, no matter if events keep coming
+            if pending_sink_since.is_some_and(|since| since.elapsed() >= debounce) {
+                pending_sink_since = None;
+                sink_poll_timeout = notif_helper.show_sink_notification(&default_sink, false);
+            }
+
+            let debounce_timeout = pending_sink_since.map(|since| {
+                MicroSeconds(debounce.saturating_sub(since.elapsed()).as_micros() as u64)
+            });
+            let poll_timeout = earliest(sink_poll_timeout, source_poll_timeout);
+            let debounce_wake = debounce_timeout.is_some()
+                && earliest(debounce_timeout, poll_timeout) == debounce_timeout;
+
+            crate::set_busy(Module::Sound, false);
+            let res = context_helper.poll_events(earliest(debounce_timeout, poll_timeout));
+            crate::set_busy(Module::Sound, true);
+
+            match res {
                 PollResult::Data(events) => {
+                    // whole batch is handled with at most one introspection per direction
+                    let mut check_sink = false;
+                    let mut check_source = false;
+
                     for event in events {
-                        match event.facility {
-                            Facility::Sink => {
-                                let current_default_sink = context_helper.get_default_sink_info();
+                        // changes of other devices don't matter, unless they can become default
+                        let other_changed = |index| {
+                            matches!(event.operation, Some(Operation::Changed))
+                                && event.index != index
+                        };
 
-                                if pa_info_eq!(current_default_sink, default_sink) {
-                                    continue;
+                        match event.facility {
+                            Facility::Sink => check_sink |= !other_changed(default_sink.index),
+                            Facility::Source => {
+                                check_source |= !other_changed(default_source.index)
+                            }
+                            // default device was switched
+                            Facility::Server => {
+                                check_sink = true;
+                                check_source = true;
+                            }
+                            Facility::SinkInput => {
+                                if matches!(
+                                    event.operation,
+                                    Some(Operation::New | Operation::Removed)
+                                ) {
+                                    last_sink_input_change = Some(Instant::now());
                                 }
+                            }
+                            _ => (),
+                        }
+                    }
+
+                    let current_default_sink = check_sink
+                        .then(|| context_helper.get_default_sink_info())
+                        .flatten();
+
+                    if let Some(current_default_sink) = current_default_sink {
+                        // e.g. flat volumes adjusting sink to a new stream
+                        let auto_change = Config::get().sound.suppress_auto_changes
+                            && last_sink_input_change
+                                .is_some_and(|at| at.elapsed() < AUTO_CHANGE_WINDOW);
 
-                                default_sink = current_default_sink;
-                                poll_timeout =
+                        if !pa_info_eq!(current_default_sink, default_sink) {
+                            default_sink = current_default_sink;
+
+                            if !auto_change && !debounce.is_zero() {
+                                pending_sink_since.get_or_insert_with(Instant::now);
+                            } else if !auto_change {
+                                sink_poll_timeout =
                                     notif_helper.show_sink_notification(&default_sink, false);
                             }
-                            Facility::Source => {
-                                let current_default_source =
-                                    context_helper.get_default_source_info();
+                        }
+                    }
 
-                                if pa_info_eq!(current_default_source, default_source) {
-                                    continue;
-                                }
+                    let current_default_source = check_source
+                        .then(|| context_helper.get_default_source_info())
+                        .flatten();
 
-                                default_source = current_default_source;
-                                notif_helper.show_source_notification(&default_source);
-                            }
-                            _ => (),
+                    if let Some(current_default_source) = current_default_source {
+                        if !pa_info_eq!(current_default_source, default_source) {
+                            default_source = current_default_source;
+                            source_poll_timeout =
+                                notif_helper.show_source_notification(&default_source, false);
                         }
                     }
                 }
+                // pending sink is shown on next iteration, battery isn't due yet
+                PollResult::Timeout if debounce_wake => (),
                 PollResult::Timeout => {
-                    let sink_info = context_helper.get_default_sink_info();
+                    if let Some(sink_info) = context_helper.get_default_sink_info() {
+                        sink_poll_timeout = notif_helper.show_sink_notification(&sink_info, true);
+                    }
 
-                    poll_timeout = notif_helper.show_sink_notification(&sink_info, true);
+                    if let Some(source_info) = context_helper.get_default_source_info() {
+                        source_poll_timeout =
+                            notif_helper.show_source_notification(&source_info, true);
+                    }
+                }
+                // appearance is read on every notification anyway, only what lives in
+                // Pulse connection is updated, so it doesn't have to be reestablished
+                PollResult::Interrupted => {
+                    let config = Config::get();
+
+                    if Arc::ptr_eq(&config, &applied_config) {
+                        continue;
+                    }
+
+                    if config.app_name != applied_config.app_name {
+                        context_helper.context.set_name(&config.app_name, |_| ());
+                    }
+
+                    if config.sound.sink_name != applied_config.sound.sink_name {
+                        match context_helper.repin_sink(config.sound.sink_name.clone()) {
+                            Some(sink) => default_sink = sink,
+                            None => warn!(
+                                "sink {} not found, keeping {}",
+                                config
+                                    .sound
+                                    .sink_name
+                                    .as_deref()
+                                    .unwrap_or("@DEFAULT_SINK@"),
+                                default_sink.name.as_deref().unwrap_or("current one")
+                            ),
+                        }
+                    }
+
+                    if config.sound.source_name != applied_config.sound.source_name {
+                        match context_helper.repin_source(config.sound.source_name.clone()) {
+                            Some(source) => default_source = source,
+                            None => warn!(
+                                "source {} not found, keeping {}",
+                                config
+                                    .sound
+                                    .source_name
+                                    .as_deref()
+                                    .unwrap_or("@DEFAULT_SOURCE@"),
+                                default_source.name.as_deref().unwrap_or("current one")
+                            ),
+                        }
+                    }
+
+                    applied_config = config;
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_to_percent() {
+        assert_eq!(pa_volume_to_percent(Volume::MUTED.0, 1), 0);
+        assert_eq!(pa_volume_to_percent(Volume::NORMAL.0, 1), 100);
+        assert_eq!(pa_volume_to_percent(Volume::NORMAL.0 / 2 * 3, 1), 150);
+        assert_eq!(pa_volume_to_percent(Volume::NORMAL.0 / 2 * 3, 0), 150);
+        assert_eq!(pa_volume_to_percent(Volume::NORMAL.0 / 100 * 47, 5), 45);
+    }
+
+    #[test]
+    fn progress_hint_is_clamped() {
+        let mut notif = Notification::new();
+
+        notif.hint(Hint::Progress(pa_volume_to_percent(
+            Volume::NORMAL.0 / 2 * 3,
+            1,
+        )));
+        assert_eq!(notif.hints.get("value"), Some(&Hint::Progress(100)));
+
+        notif.hint(Hint::Progress(-1));
+        assert_eq!(notif.hints.get("value"), Some(&Hint::Progress(0)));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::config::{Config, Thermal};
+use crate::netlink::utils as ev_utils;
+use crate::netlink::{NetlinkError, NetlinkHandle};
+use crate::notif::{self, Notification, Timeout, Urgency};
+use crate::Module;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+// °C, zone has to cool down this much below threshold before it can warn again
+const HYSTERESIS: i32 = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Level {
+    Normal,
+    Warn,
+    Critical,
+}
+
+struct Zone {
+    device: PathBuf,
+    // e.g. x86_pkg_temp, acpitz
+    kind: String,
+    level: Level,
+}
+
+impl Zone {
+    // sysfs reports millidegrees
+    fn temp(&self) -> Option<i32> {
+        let temp = std::fs::read_to_string(self.device.join("temp")).ok()?;
+
+        temp.trim().parse::<i32>().ok().map(|temp| temp / 1000)
+    }
+
+    fn level(&self, thermal_config: &Thermal, temp: i32) -> Level {
+        let reached = |threshold: i32, level: Level| {
+            temp >= threshold || (self.level >= level && temp > threshold - HYSTERESIS)
+        };
+
+        if reached(thermal_config.critical_at, Level::Critical) {
+            Level::Critical
+        } else if reached(thermal_config.warn_at, Level::Warn) {
+            Level::Warn
+        } else {
+            Level::Normal
+        }
+    }
+}
+
+fn find_zones(targets: &[String]) -> Vec<Zone> {
+    ev_utils::sysfs_devices("thermal")
+        .into_iter()
+        .filter_map(|device| {
+            let name = device.file_name()?.to_str()?.to_owned();
+            let kind = std::fs::read_to_string(device.join("type")).ok()?;
+            let kind = kind.trim().to_owned();
+
+            // cooling devices live in the same class
+            if !name.starts_with("thermal_zone") {
+                return None;
+            }
+
+            if !targets.is_empty() && !targets.contains(&name) && !targets.contains(&kind) {
+                return None;
+            }
+
+            Some(Zone {
+                device,
+                kind,
+                level: Level::Normal,
+            })
+        })
+        .collect()
+}
+
+fn show(notif: &mut Notification, thermal_config: &Thermal, zone: &Zone, temp: i32) {
+    let temp = temp.to_string();
+    let values = [
+        ("name", "Temperature"),
+        ("value", temp.as_str()),
+        ("zone", zone.kind.as_str()),
+    ];
+    let body = format!("{} {temp}°C", zone.kind);
+
+    notif
+        .app_name(&notif::app_name(&thermal_config.app_name))
+        .summary(&thermal_config.format.summary("Temperature", &values))
+        .body(&thermal_config.format.body(&body, &values))
+        .icon_in(&thermal_config.icon_path, &thermal_config.icon);
+
+    // too hot is as important as low battery
+    match zone.level {
+        Level::Critical => notif.urgency(Urgency::Critical).timeout(Timeout::Never),
+        _ => notif
+            .urgency(thermal_config.format.urgency())
+            .timeout(thermal_config.format.timeout(Timeout::Millis(5000))),
+    };
+
+    notif.show();
+}
+
+pub fn icons() -> Vec<String> {
+    let thermal_config = &Config::get().thermal;
+
+    vec![notif::join_icon(
+        &thermal_config.icon_path,
+        &thermal_config.icon,
+    )]
+}
+
+pub fn test_notification() -> Notification {
+    let config = Config::get();
+    let thermal_config = &config.thermal;
+    let mut notif = Notification::new();
+
+    notif
+        .module(Module::Thermal)
+        .app_name(&notif::app_name(&thermal_config.app_name))
+        .summary("Temperature")
+        .body("x86_pkg_temp 90°C")
+        .urgency(thermal_config.format.urgency())
+        .timeout(thermal_config.format.timeout(Timeout::Millis(5000)))
+        .icon_in(&thermal_config.icon_path, &thermal_config.icon);
+
+    notif
+}
+
+pub fn routine() -> impl crate::Routine {
+    || {
+        let mut applied_zones = Config::get().thermal.zones.clone();
+        let mut zones = find_zones(&applied_zones);
+
+        if zones.is_empty() {
+            info!("no thermal zone found, thermal module is not running");
+            return;
+        }
+
+        let mut notif = Notification::new();
+        let mut handle = NetlinkHandle::new().unwrap().subscribe("thermal");
+
+        notif.module(Module::Thermal).replace_tag("sun-thermal");
+
+        loop {
+            let config = Config::get();
+            let thermal_config = &config.thermal;
+
+            if thermal_config.off || crate::shutting_down() {
+                break;
+            }
+
+            // zones changed on reload, ones still watched keep their level so they don't warn again
+            if thermal_config.zones != applied_zones {
+                applied_zones = thermal_config.zones.clone();
+
+                let mut found = find_zones(&applied_zones);
+
+                for zone in found.iter_mut() {
+                    if let Some(old) = zones.iter().find(|old| old.device == zone.device) {
+                        zone.level = old.level;
+                    }
+                }
+
+                zones = found;
+
+                if zones.is_empty() {
+                    warn!("no thermal zone matches zones, thermal module is not running");
+                    break;
+                }
+            }
+
+            // hottest zone, there is no event to repeat
+            if crate::take_refresh(Module::Thermal) {
+                let hottest = zones
+                    .iter()
+                    .filter_map(|zone| Some((zone, zone.temp()?)))
+                    .max_by_key(|(_, temp)| *temp);
+
+                if let Some((zone, temp)) = hottest {
+                    show(&mut notif, thermal_config, zone, temp);
+                }
+            }
+
+            crate::set_busy(Module::Thermal, false);
+            let res = handle.next_event(thermal_config.poll_timeout as i32);
+            crate::set_busy(Module::Thermal, true);
+
+            match res {
+                // trip point crossed or time to poll, either way every zone is read
+                Ok(_) | Err(NetlinkError::Timeout) => {
+                    for zone in zones.iter_mut() {
+                        let Some(temp) = zone.temp() else {
+                            continue;
+                        };
+                        let level = zone.level(thermal_config, temp);
+                        let rose = level > zone.level;
+
+                        zone.level = level;
+
+                        if rose {
+                            show(&mut notif, thermal_config, zone, temp);
+                        }
+                    }
+                }
+                Err(NetlinkError::IO(ErrorKind::Interrupted)) => (),
+                Err(err) if err.is_recoverable() => {
+                    warn!("netlink socket overrun or reset, reconnecting");
+                    handle.reconnect().unwrap_or_else(|err| panic!("{err}"));
+                }
+                Err(NetlinkError::IO(kind)) => panic!("{kind:?}"),
+                Err(_) => (),
+            }
+        }
+    }
+}